@@ -0,0 +1,528 @@
+//! The Binary Object Store (BOS) descriptor (USB 3.2 §9.6.2), where devices
+//! from USB 2.01 on list their capabilities, and decoding of the
+//! capabilities hubs report.
+
+use std::time::Duration;
+
+/// The descriptor type of the BOS descriptor, as sent in GetDescriptor.
+pub const BOS_DESCRIPTOR_TYPE: u8 = 0x0f;
+
+/// The BOS descriptor header: `bLength`, `bDescriptorType`, `wTotalLength`
+/// and `bNumDeviceCaps`.
+pub const BOS_HEADER_SIZE: u16 = 5;
+
+const DEVICE_CAPABILITY_TYPE: u8 = 0x10;
+
+const USB2_EXTENSION_CAPABILITY: u8 = 0x02;
+const SUPERSPEED_CAPABILITY: u8 = 0x03;
+const CONTAINER_ID_CAPABILITY: u8 = 0x04;
+const SUPERSPEED_PLUS_CAPABILITY: u8 = 0x0a;
+const BILLBOARD_CAPABILITY: u8 = 0x0d;
+
+/// The UUID a device reports in its Container ID capability. Every function
+/// of one physical device, such as both halves of a USB 3 hub, reports the
+/// same one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContainerId(pub [u8; 16]);
+
+impl core::fmt::Display for ContainerId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, byte) in self.0.iter().enumerate() {
+            if matches!(index, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The total length of the BOS descriptor, from its header. Returns `None`
+/// if `header` isn't the start of a BOS descriptor.
+pub fn total_length(header: &[u8]) -> Option<u16> {
+    if header.len() < BOS_HEADER_SIZE as usize || header[1] != BOS_DESCRIPTOR_TYPE {
+        return None;
+    }
+    Some(u16::from_le_bytes([header[2], header[3]]))
+}
+
+/// Each device capability in a BOS descriptor, as its capability type and
+/// the bytes that follow it. Stops at the first malformed capability.
+pub fn capabilities(bos: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut rest = bos.get(BOS_HEADER_SIZE as usize..).unwrap_or_default();
+    std::iter::from_fn(move || {
+        let length = *rest.first()? as usize;
+        if length < 3 || length > rest.len() || rest[1] != DEVICE_CAPABILITY_TYPE {
+            return None;
+        }
+        let (capability, next) = rest.split_at(length);
+        rest = next;
+        Some((capability[2], &capability[3..]))
+    })
+}
+
+/// The Container ID in a BOS descriptor, if it has one.
+pub fn container_id(bos: &[u8]) -> Option<ContainerId> {
+    parse(bos)
+        .into_iter()
+        .find_map(|capability| match capability {
+            Capability::ContainerId(id) => Some(id),
+            _ => None,
+        })
+}
+
+/// Decode every device capability in a BOS descriptor.
+pub fn parse(bos: &[u8]) -> Vec<Capability> {
+    capabilities(bos)
+        .map(|(kind, data)| Capability::parse(kind, data))
+        .collect()
+}
+
+/// A device capability from a BOS descriptor. Capabilities that hubctl
+/// doesn't decode, or that are too short to decode, are kept as they are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Capability {
+    /// USB 2.0 Extension (USB 2.0 LPM ECN), describing Link Power
+    /// Management.
+    Usb2Extension(Usb2Extension),
+    /// SuperSpeed USB (USB 3.2 §9.6.2.2).
+    SuperSpeed(SuperSpeed),
+    ContainerId(ContainerId),
+    /// SuperSpeedPlus USB (USB 3.2 §9.6.2.5).
+    SuperSpeedPlus(SuperSpeedPlus),
+    /// Billboard (USB Billboard Device Class 1.2 §3.1.6.2), reported by
+    /// devices that couldn't enter a USB Type-C alternate mode.
+    Billboard(Billboard),
+    Other {
+        kind: u8,
+        data: Vec<u8>,
+    },
+}
+
+impl Capability {
+    /// Decode a capability from its type and the bytes after it.
+    pub fn parse(kind: u8, data: &[u8]) -> Self {
+        let decoded = match kind {
+            USB2_EXTENSION_CAPABILITY => Usb2Extension::parse(data).map(Capability::Usb2Extension),
+            SUPERSPEED_CAPABILITY => SuperSpeed::parse(data).map(Capability::SuperSpeed),
+            CONTAINER_ID_CAPABILITY => data
+                .get(1..17)
+                .and_then(|id| id.try_into().ok())
+                .map(|id| Capability::ContainerId(ContainerId(id))),
+            SUPERSPEED_PLUS_CAPABILITY => {
+                SuperSpeedPlus::parse(data).map(Capability::SuperSpeedPlus)
+            }
+            BILLBOARD_CAPABILITY => Billboard::parse(data).map(Capability::Billboard),
+            _ => None,
+        };
+        decoded.unwrap_or_else(|| Capability::Other {
+            kind,
+            data: data.to_vec(),
+        })
+    }
+}
+
+impl core::fmt::Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Capability::Usb2Extension(extension) => write!(f, "USB 2.0 Extension: {extension}"),
+            Capability::SuperSpeed(superspeed) => write!(f, "SuperSpeed USB: {superspeed}"),
+            Capability::ContainerId(id) => write!(f, "Container ID: {id}"),
+            Capability::SuperSpeedPlus(plus) => write!(f, "SuperSpeedPlus USB: {plus}"),
+            Capability::Billboard(billboard) => write!(f, "Billboard: {billboard}"),
+            Capability::Other { kind, data } => match capability_name(*kind) {
+                Some(name) => write!(f, "{name} ({} bytes, not decoded)", data.len()),
+                None => write!(f, "Unknown capability {kind:#04x} ({} bytes)", data.len()),
+            },
+        }
+    }
+}
+
+/// The names of capabilities hubctl recognises but doesn't decode.
+fn capability_name(kind: u8) -> Option<&'static str> {
+    Some(match kind {
+        0x01 => "Wireless USB",
+        0x05 => "Platform",
+        0x06 => "Power Delivery",
+        0x07 => "Battery Info",
+        0x08 => "PD Consumer Port",
+        0x09 => "PD Provider Port",
+        0x0b => "Precision Time Measurement",
+        0x0c => "Wireless USB Extension",
+        0x0e => "Billboard Alternate Mode",
+        0x0f => "Authentication",
+        0x10 => "Billboard Extension",
+        0x11 => "Configuration Summary",
+        0x12 => "Firmware Status",
+        _ => return None,
+    })
+}
+
+/// Link Power Management support, from the USB 2.0 Extension capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usb2Extension {
+    /// Whether the device supports LPM, letting the host put its link to
+    /// sleep (L1) between transfers.
+    pub lpm: bool,
+    /// Whether the device takes Best Effort Service Latency values, rather
+    /// than the older HIRD values, in LPM requests.
+    pub besl: bool,
+    /// The BESL the device would like the host to use, if it gave one.
+    pub baseline_besl: Option<u8>,
+    /// The BESL the device would like for deeper sleep, if it gave one.
+    pub deep_besl: Option<u8>,
+}
+
+impl Usb2Extension {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let attributes = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+        let field = |valid: u32, shift: u32| {
+            (attributes & 1 << valid != 0).then_some((attributes >> shift & 0xf) as u8)
+        };
+        Some(Usb2Extension {
+            lpm: attributes & 1 << 1 != 0,
+            besl: attributes & 1 << 2 != 0,
+            baseline_besl: field(3, 8),
+            deep_besl: field(4, 12),
+        })
+    }
+}
+
+impl core::fmt::Display for Usb2Extension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.lpm {
+            return write!(f, "LPM not supported");
+        }
+        write!(f, "LPM supported")?;
+        if self.besl {
+            write!(f, ", with BESL")?;
+        }
+        if let Some(besl) = self.baseline_besl {
+            write!(f, ", baseline BESL {besl}")?;
+        }
+        if let Some(besl) = self.deep_besl {
+            write!(f, ", deep BESL {besl}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The SuperSpeed USB capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuperSpeed {
+    /// Whether the device can generate Latency Tolerance Messages.
+    pub ltm: bool,
+    /// The speeds the device works at, as in `wSpeedsSupported`: bit 0 is
+    /// low speed, then full speed, high speed and 5 Gb/s.
+    pub speeds: u16,
+    /// The lowest speed at which all of the device works, as a bit number
+    /// of `speeds`.
+    pub lowest_full_speed: u8,
+    /// How long the device takes to leave U1.
+    pub u1_exit_latency: Duration,
+    /// How long the device takes to leave U2.
+    pub u2_exit_latency: Duration,
+}
+
+const SPEED_NAMES: [&str; 4] = ["low speed", "full speed", "high speed", "5 Gb/s"];
+
+impl SuperSpeed {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let data = data.get(..7)?;
+        Some(SuperSpeed {
+            ltm: data[0] & 1 << 1 != 0,
+            speeds: u16::from_le_bytes([data[1], data[2]]),
+            lowest_full_speed: data[3],
+            u1_exit_latency: Duration::from_micros(data[4].into()),
+            u2_exit_latency: Duration::from_micros(u16::from_le_bytes([data[5], data[6]]).into()),
+        })
+    }
+}
+
+impl core::fmt::Display for SuperSpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let speeds: Vec<&str> = SPEED_NAMES
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| self.speeds & 1 << bit != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        write!(f, "{}", speeds.join(", "))?;
+        if let Some(speed) = SPEED_NAMES.get(usize::from(self.lowest_full_speed)) {
+            write!(f, " (fully working from {speed})")?;
+        }
+        write!(
+            f,
+            ", U1 exit latency {:?}, U2 exit latency {:?}",
+            self.u1_exit_latency, self.u2_exit_latency
+        )?;
+        if self.ltm {
+            write!(f, ", LTM")?;
+        }
+        Ok(())
+    }
+}
+
+/// The SuperSpeedPlus USB capability, listing the speeds of the device's
+/// sublinks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuperSpeedPlus {
+    pub sublink_speeds: Vec<SublinkSpeed>,
+}
+
+impl SuperSpeedPlus {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let attributes = u32::from_le_bytes(data.get(1..5)?.try_into().ok()?);
+        let count = (attributes & 0x1f) as usize + 1;
+        let sublink_speeds = data
+            .get(9..9 + count * 4)?
+            .chunks_exact(4)
+            .map(|attribute| SublinkSpeed::parse(u32::from_le_bytes(attribute.try_into().unwrap())))
+            .collect();
+        Some(SuperSpeedPlus { sublink_speeds })
+    }
+}
+
+impl core::fmt::Display for SuperSpeedPlus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, speed) in self.sublink_speeds.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{speed}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Which way a sublink speed applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SublinkType {
+    Symmetric,
+    Receive,
+    Transmit,
+}
+
+/// One of the speeds a SuperSpeedPlus device's sublinks run at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SublinkSpeed {
+    /// The Sublink Speed ID the device uses for this speed.
+    pub id: u8,
+    /// The lane speed, in bits per second.
+    pub bits_per_second: u64,
+    pub kind: SublinkType,
+    /// Whether the speed uses the SuperSpeedPlus protocol rather than the
+    /// SuperSpeed one.
+    pub superspeed_plus: bool,
+}
+
+impl SublinkSpeed {
+    fn parse(attribute: u32) -> Self {
+        let exponent = attribute >> 4 & 0x3;
+        let mantissa = u64::from(attribute >> 16);
+        let kind = match attribute >> 6 & 0x3 {
+            0b01 => SublinkType::Receive,
+            0b11 => SublinkType::Transmit,
+            _ => SublinkType::Symmetric,
+        };
+        SublinkSpeed {
+            id: (attribute & 0xf) as u8,
+            bits_per_second: mantissa * 1000u64.pow(exponent),
+            kind,
+            superspeed_plus: attribute >> 14 & 0x3 == 1,
+        }
+    }
+}
+
+impl core::fmt::Display for SublinkSpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let speed = self.bits_per_second as f64;
+        match self.bits_per_second {
+            1_000_000_000.. => write!(f, "{} Gb/s", speed / 1e9)?,
+            1_000_000.. => write!(f, "{} Mb/s", speed / 1e6)?,
+            _ => write!(f, "{} b/s", self.bits_per_second)?,
+        }
+        match self.kind {
+            SublinkType::Symmetric => {}
+            SublinkType::Receive => write!(f, " receive")?,
+            SublinkType::Transmit => write!(f, " transmit")?,
+        }
+        write!(
+            f,
+            " ({}, ID {})",
+            if self.superspeed_plus {
+                "SuperSpeedPlus"
+            } else {
+                "SuperSpeed"
+            },
+            self.id
+        )
+    }
+}
+
+/// The Billboard capability, describing the USB Type-C alternate modes the
+/// device offers and whether they could be entered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Billboard {
+    /// The version of the Billboard specification, in BCD.
+    pub version: u16,
+    /// The index of the preferred mode in `modes`.
+    pub preferred: u8,
+    pub modes: Vec<AlternateMode>,
+}
+
+/// One alternate mode from the Billboard capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlternateMode {
+    /// The Standard or Vendor ID the mode belongs to, such as 0xff01 for
+    /// DisplayPort.
+    pub svid: u16,
+    pub mode: u8,
+    pub state: AlternateModeState,
+}
+
+/// How entering an alternate mode went, from `bmConfigured`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlternateModeState {
+    Error,
+    NotAttempted,
+    Failed,
+    Configured,
+}
+
+impl core::fmt::Display for AlternateModeState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlternateModeState::Error => write!(f, "unspecified error"),
+            AlternateModeState::NotAttempted => write!(f, "not attempted or exited"),
+            AlternateModeState::Failed => write!(f, "failed"),
+            AlternateModeState::Configured => write!(f, "configured"),
+        }
+    }
+}
+
+impl Billboard {
+    /// `bmConfigured` is 32 bytes, with two bits for each mode.
+    const MODES_OFFSET: usize = 41;
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        let count = usize::from(*data.get(1)?);
+        let configured = data.get(5..37)?;
+        let modes = data
+            .get(Self::MODES_OFFSET..Self::MODES_OFFSET + count * 4)?
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(index, mode)| AlternateMode {
+                svid: u16::from_le_bytes([mode[0], mode[1]]),
+                mode: mode[2],
+                state: match configured
+                    .get(index / 4)
+                    .map(|bits| bits >> (index % 4 * 2) & 0x3)
+                {
+                    Some(0b01) => AlternateModeState::NotAttempted,
+                    Some(0b10) => AlternateModeState::Failed,
+                    Some(0b11) => AlternateModeState::Configured,
+                    _ => AlternateModeState::Error,
+                },
+            })
+            .collect();
+        Some(Billboard {
+            version: u16::from_le_bytes([data[37], data[38]]),
+            preferred: data[2],
+            modes,
+        })
+    }
+}
+
+impl core::fmt::Display for Billboard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "version {:x}.{:02x}",
+            self.version >> 8,
+            self.version & 0xff
+        )?;
+        for (index, mode) in self.modes.iter().enumerate() {
+            write!(
+                f,
+                ", SVID {:04x} mode {} {}",
+                mode.svid, mode.mode, mode.state
+            )?;
+            if index == usize::from(self.preferred) {
+                write!(f, " (preferred)")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_container_id() {
+        let mut bos = vec![5, 0x0f, 0, 0, 2];
+        // USB 2.0 Extension, with LPM supported
+        bos.extend([7, 0x10, 0x02, 0x02, 0, 0, 0]);
+        bos.extend([20, 0x10, 0x04, 0]);
+        bos.extend(0x10..0x20);
+        bos[2] = bos.len() as u8;
+
+        assert_eq!(total_length(&bos[..5]), Some(bos.len() as u16));
+        assert_eq!(capabilities(&bos).count(), 2);
+        let id = container_id(&bos).unwrap();
+        assert_eq!(id.to_string(), "10111213-1415-1617-1819-1a1b1c1d1e1f");
+
+        assert_eq!(container_id(&bos[..12]), None);
+        assert_eq!(total_length(&[9, 0x02, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn decodes_capabilities() {
+        let mut bos = vec![5, 0x0f, 0, 0, 5];
+        // SuperSpeed: full speed and up, U1 in 10 µs, U2 in 2047 µs
+        bos.extend([10, 0x10, 0x03, 0, 0x0e, 0, 1, 10, 0xff, 0x07]);
+        // SuperSpeedPlus: 10 Gb/s Gen 2 and 5 Gb/s Gen 1
+        bos.extend([20, 0x10, 0x0a, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+        bos.extend([0x30, 0x40, 10, 0, 0x31, 0, 5, 0]);
+        // Billboard, with DisplayPort configured
+        bos.extend([48, 0x10, 0x0d, 0, 1, 0, 0, 0, 0b11]);
+        bos.extend([0; 31]);
+        bos.extend([0x21, 0x01, 0, 0, 0x01, 0xff, 0, 0]);
+        bos.extend([5, 0x10, 0x05, 0, 0]);
+        bos.extend([5, 0x10, 0x42, 0, 0]);
+
+        let decoded: Vec<String> = parse(&bos).iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            decoded,
+            [
+                "SuperSpeed USB: full speed, high speed, 5 Gb/s (fully working from full speed), U1 exit latency 10µs, U2 exit latency 2.047ms",
+                "SuperSpeedPlus USB: 10 Gb/s (SuperSpeedPlus, ID 0), 5 Gb/s (SuperSpeed, ID 1)",
+                "Billboard: version 1.21, SVID ff01 mode 0 configured (preferred)",
+                "Platform (2 bytes, not decoded)",
+                "Unknown capability 0x42 (2 bytes)",
+            ]
+        );
+
+        let lpm = Capability::parse(0x02, &[0x1e, 0x42, 0, 0]);
+        assert_eq!(
+            lpm,
+            Capability::Usb2Extension(Usb2Extension {
+                lpm: true,
+                besl: true,
+                baseline_besl: Some(2),
+                deep_besl: Some(4),
+            })
+        );
+        assert_eq!(
+            lpm.to_string(),
+            "USB 2.0 Extension: LPM supported, with BESL, baseline BESL 2, deep BESL 4"
+        );
+        // Too short to decode
+        assert!(matches!(
+            Capability::parse(0x03, &[0, 0x0e]),
+            Capability::Other { kind: 0x03, .. }
+        ));
+    }
+}
@@ -0,0 +1,465 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum, error::ErrorKind};
+
+use hubctl::{
+    recovery::RecoveryPolicy,
+    selector::{DeviceMatch, HubSelector, Location, PortSelector, VidPid},
+    status::{Change, HubChange, LinkState},
+};
+
+/// Control power to the ports of USB hubs.
+///
+/// With no subcommand, hubctl presents an interactive menu.
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    /// After switching power, check that the hub actually did it
+    #[arg(long, global = true)]
+    pub verify: bool,
+
+    /// How `list`, `status`, `topology` and `watch` print their results
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
+    /// Save every control transfer sent to a hub, and what came back, to
+    /// this file for replaying later
+    #[arg(long, global = true, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    /// Answer from a file saved with `--record` instead of the hubs on this
+    /// machine. Fails if hubctl asks for anything that wasn't recorded.
+    #[arg(long, global = true, value_name = "FILE", conflicts_with = "record")]
+    pub replay: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// The error for a command that `--replay` can't play back, as devices
+    /// coming and going aren't recorded.
+    pub fn check_replay(&self) -> Result<(), clap::Error> {
+        let command = match &self.command {
+            Some(Command::Watch(_)) => "watch",
+            Some(Command::Cycle(args)) if args.wait => "cycle --wait",
+            _ => return Ok(()),
+        };
+        Err(usage_error(
+            ErrorKind::ArgumentConflict,
+            &format!("{command} can't be replayed, as hotplug events aren't recorded"),
+        ))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Human-readable text
+    Text,
+    /// JSON, in the schema described in the README. Only for `list` and
+    /// `status`, and `watch`, which prints one JSON object per line.
+    Json,
+    /// A Graphviz DOT graph. Only for `topology`.
+    Dot,
+    /// A Mermaid flowchart. Only for `topology`.
+    Mermaid,
+}
+
+impl Format {
+    /// The error for a command that can't print its results this way.
+    pub fn unsupported(self, command: &str) -> clap::Error {
+        let name = self
+            .to_possible_value()
+            .map(|value| value.get_name().to_owned())
+            .unwrap_or_default();
+        usage_error(
+            ErrorKind::InvalidValue,
+            &format!("{command} can't be printed with --format {name}"),
+        )
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// List every hub along with the devices attached to its ports
+    List,
+
+    /// Show how hubs and devices are cabled together, with the power state
+    /// of each hub port
+    Topology,
+
+    /// Show the power state of ports. Every port of every hub if no hub is
+    /// given.
+    Status(StatusArgs),
+
+    /// Show what a hub says about itself, including the capabilities in its
+    /// BOS descriptor such as Link Power Management support
+    Info(Target),
+
+    /// Turn power on to a port
+    On(PortArgs),
+
+    /// Turn power off to a port
+    Off(PortArgs),
+
+    /// Invert the power state of a port
+    Toggle(PortArgs),
+
+    /// Turn a port off, wait, then turn it back on
+    Cycle(CycleArgs),
+
+    /// Reset the device on a port without cutting its power: a bus reset on
+    /// USB 2 hubs, or a warm reset on SuperSpeed hubs
+    Reset(Target),
+
+    /// Suspend the device on a port, leaving it powered
+    Suspend(Target),
+
+    /// Resume a suspended port
+    Resume(Target),
+
+    /// Blink the indicator LED of a port, to find it on the hub
+    Identify(IdentifyArgs),
+
+    /// Move the link of a SuperSpeed port to another state, such as
+    /// `disabled` to cut the device off without switching power
+    Link(LinkArgs),
+
+    /// Print devices connecting and disconnecting, and ports changing power
+    /// or over-current state, until interrupted
+    Watch(WatchArgs),
+}
+
+/// A hub, and possibly one of its ports.
+///
+/// A hub is either given by its index, as printed by `hubctl list`, or by
+/// one of the selector options, and a port either by its number or by the
+/// device attached to it. Positional arguments fill in whichever of the hub
+/// and port weren't given as options.
+#[derive(Args)]
+pub struct Target {
+    /// Hub index, as printed by `hubctl list`. Leave this out when selecting
+    /// the hub with an option.
+    hub: Option<usize>,
+
+    /// Port number, starting at 1
+    port: Option<u8>,
+
+    /// Select the hub at this location, such as `3-1.4` for port 4 of the
+    /// hub on port 1 of bus 3
+    #[arg(long, value_name = "BUS-PORTS")]
+    location: Option<Location>,
+
+    /// Select the hub with this serial number
+    #[arg(long)]
+    serial: Option<String>,
+
+    /// Select the hub with this vendor and product ID, such as `0424:2514`.
+    /// Add `#N` to pick the Nth of several identical hubs, counting from 0.
+    #[arg(long, value_name = "VID:PID[#N]")]
+    vid_pid: Option<VidPid>,
+
+    /// Select the hub that this device is plugged into. The device is given
+    /// as `VID:PID[:SERIAL]`, `serial=SERIAL` or `product=NAME`.
+    #[arg(long, value_name = "DEVICE")]
+    containing: Option<DeviceMatch>,
+
+    /// Select the port that this device is plugged into, given the same way
+    /// as for `--containing`. Also selects the hub if no other hub is given.
+    #[arg(long, value_name = "DEVICE")]
+    device: Option<DeviceMatch>,
+}
+
+impl Target {
+    /// Work out which hub and port were asked for.
+    pub fn resolve(&self) -> Result<(Option<HubSelector>, Option<PortSelector>), clap::Error> {
+        let mut selectors: Vec<HubSelector> = [
+            self.location.clone().map(HubSelector::Location),
+            self.serial.clone().map(HubSelector::Serial),
+            self.vid_pid.clone().map(HubSelector::VidPid),
+            self.containing.clone().map(HubSelector::Containing),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if selectors.len() > 1 {
+            return Err(usage_error(
+                ErrorKind::ArgumentConflict,
+                "only one of --location, --serial, --vid-pid and --containing may be given",
+            ));
+        }
+
+        // Positional arguments fill in whatever wasn't given as an option.
+        let mut positional = self.hub.into_iter().chain(self.port.map(usize::from));
+        let mut hub = selectors
+            .pop()
+            .or_else(|| positional.next().map(HubSelector::Index));
+        let port = match &self.device {
+            Some(device) => {
+                hub.get_or_insert_with(|| HubSelector::Containing(device.clone()));
+                Some(PortSelector::Device(device.clone()))
+            }
+            None => positional
+                .next()
+                .map(|port| {
+                    u8::try_from(port).map(PortSelector::Number).map_err(|e| {
+                        usage_error(ErrorKind::ValueValidation, &format!("invalid port: {e}"))
+                    })
+                })
+                .transpose()?,
+        };
+        if positional.next().is_some() {
+            return Err(usage_error(
+                ErrorKind::TooManyValues,
+                "too many positional arguments, as the hub or port was given with an option",
+            ));
+        }
+        Ok((hub, port))
+    }
+
+    /// Like [`Target::resolve`], but only a hub may be given.
+    pub fn resolve_hub(&self) -> Result<HubSelector, clap::Error> {
+        match self.resolve()? {
+            (Some(hub), None) => Ok(hub),
+            (None, _) => Err(usage_error(
+                ErrorKind::MissingRequiredArgument,
+                "a hub is required",
+            )),
+            (Some(_), Some(_)) => Err(usage_error(
+                ErrorKind::TooManyValues,
+                "only a hub may be given, not a port",
+            )),
+        }
+    }
+
+    /// Like [`Target::resolve`], but both the hub and port must be given.
+    pub fn resolve_port(&self) -> Result<(HubSelector, PortSelector), clap::Error> {
+        match self.resolve()? {
+            (Some(hub), Some(port)) => Ok((hub, port)),
+            _ => Err(usage_error(
+                ErrorKind::MissingRequiredArgument,
+                "both a hub and a port are required",
+            )),
+        }
+    }
+}
+
+fn usage_error(kind: ErrorKind, message: &str) -> clap::Error {
+    Cli::command().error(kind, message)
+}
+
+#[derive(Args)]
+pub struct StatusArgs {
+    #[command(flatten)]
+    pub target: Target,
+
+    /// Once the status has been read, acknowledge these change bits so they
+    /// read as unchanged until they next change. Given as a comma-separated
+    /// list such as `connection,over-current`, or `all`. The hub's own bits
+    /// are acknowledged when no port is given.
+    #[arg(long, value_name = "CHANGES", value_parser = parse_changes)]
+    pub acknowledge: Option<Changes>,
+}
+
+/// Change bits given on the command line. A name such as `over-current`
+/// that both ports and hubs have picks both.
+#[derive(Clone)]
+pub struct Changes {
+    pub ports: Vec<Change>,
+    pub hub: Vec<HubChange>,
+}
+
+fn parse_changes(value: &str) -> Result<Changes, String> {
+    if value == "all" {
+        return Ok(Changes {
+            ports: Change::ALL.to_vec(),
+            hub: HubChange::ALL.to_vec(),
+        });
+    }
+    let mut changes = Changes {
+        ports: vec![],
+        hub: vec![],
+    };
+    for name in value.split(',') {
+        match (name.parse::<Change>(), name.parse::<HubChange>()) {
+            (Err(e), Err(_)) => return Err(format!("{e}, or local-power")),
+            (port, hub) => {
+                changes.ports.extend(port.ok());
+                changes.hub.extend(hub.ok());
+            }
+        }
+    }
+    Ok(changes)
+}
+
+#[derive(Args)]
+pub struct PortArgs {
+    #[command(flatten)]
+    pub target: Target,
+
+    /// Switch power even if the hub gangs its ports together or doesn't
+    /// support power switching at all
+    #[arg(long)]
+    pub force: bool,
+
+    /// Only switch the port on the selected hub, and not on the other half
+    /// of a USB 3 hub
+    #[arg(long)]
+    pub no_companion: bool,
+}
+
+#[derive(Args)]
+pub struct CycleArgs {
+    #[command(flatten)]
+    pub port: PortArgs,
+
+    /// How long to leave the port off, such as `500ms` or `2s`. Defaults to
+    /// one second or the hub's power-on-to-power-good time, if that's longer.
+    #[arg(long, value_parser = parse_duration)]
+    pub off_time: Option<Duration>,
+
+    /// Wait for a device to enumerate on the port once it's powered again
+    #[arg(long)]
+    pub wait: bool,
+
+    /// How long `--wait` waits for the device to come back
+    #[arg(long, value_parser = parse_duration, default_value = "10s")]
+    pub timeout: Duration,
+}
+
+#[derive(Args)]
+pub struct IdentifyArgs {
+    #[command(flatten)]
+    pub target: Target,
+
+    /// How long to blink the indicator for
+    #[arg(long, value_parser = parse_duration, default_value = "5s")]
+    pub duration: Duration,
+
+    /// Try even if the hub doesn't advertise port indicators
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args)]
+pub struct LinkArgs {
+    #[command(flatten)]
+    pub target: Target,
+
+    /// The state to move the link to: `u0` to `u3`, `disabled`, or
+    /// `rx-detect` to enable a disabled port again
+    #[arg(long, value_name = "STATE")]
+    pub to: LinkState,
+}
+
+#[derive(Args)]
+pub struct WatchArgs {
+    /// How often to read the status of every port of hubs whose status
+    /// change endpoint can't be used
+    #[arg(long, value_parser = parse_duration, default_value = "1s")]
+    pub interval: Duration,
+
+    /// Power ports that trip over-current again, up to this many times
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub recover: u32,
+
+    /// How long `--recover` waits before powering a port again, doubling
+    /// after each attempt
+    #[arg(long, value_parser = parse_duration, default_value = "1s")]
+    pub backoff: Duration,
+}
+
+impl WatchArgs {
+    /// What to do about over-current, if anything.
+    pub fn recovery_policy(&self) -> Option<RecoveryPolicy> {
+        (self.recover > 0).then(|| RecoveryPolicy::new(self.recover, self.backoff))
+    }
+}
+
+/// Parse a duration such as `500ms`, `2s` or `1.5`, which is in seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, scale) = if let Some(ms) = value.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(s) = value.strip_suffix('s') {
+        (s, 1.0)
+    } else {
+        (value, 1.0)
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid duration {value:?}: {e}"))?;
+    Duration::try_from_secs_f64(number * scale)
+        .map_err(|e| format!("invalid duration {value:?}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_parse() {
+        for (text, expected) in [
+            ("500ms", Duration::from_millis(500)),
+            ("2s", Duration::from_secs(2)),
+            ("1.5s", Duration::from_millis(1500)),
+            ("0.25", Duration::from_millis(250)),
+            ("3", Duration::from_secs(3)),
+            ("3 s", Duration::from_secs(3)),
+            ("0", Duration::ZERO),
+            ("0ms", Duration::ZERO),
+        ] {
+            assert_eq!(parse_duration(text), Ok(expected), "{text}");
+        }
+        for text in [
+            "",
+            "s",
+            "ms",
+            "abc",
+            "1m",
+            "1h",
+            "-1s",
+            "inf",
+            "NaN",
+            "2 seconds",
+        ] {
+            assert!(parse_duration(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn changes_parse() {
+        let parse = |text| {
+            let changes = parse_changes(text).unwrap();
+            (changes.ports, changes.hub)
+        };
+        assert_eq!(
+            parse("all"),
+            (Change::ALL.to_vec(), HubChange::ALL.to_vec())
+        );
+        assert_eq!(
+            parse("connection,C_PORT_RESET"),
+            (vec![Change::Connection, Change::Reset], vec![])
+        );
+        assert_eq!(
+            parse("local-power,C_HUB_OVER_CURRENT"),
+            (vec![], vec![HubChange::LocalPower, HubChange::OverCurrent])
+        );
+        // Both ports and hubs have an over-current bit.
+        assert_eq!(
+            parse("over-current"),
+            (vec![Change::OverCurrent], vec![HubChange::OverCurrent])
+        );
+
+        for text in [
+            "",
+            "bogus",
+            "connection,bogus",
+            "all,connection",
+            "connection,",
+        ] {
+            let error = parse_changes(text).err().unwrap();
+            assert!(error.ends_with(", or local-power"), "{text}: {error}");
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Pairing the two halves of USB 3 hubs.
+//!
+//! A USB 3 hub shows up as two hubs, each on its own bus: a USB 2 hub for
+//! low-, full- and high-speed devices, and a SuperSpeed hub. Port N of one
+//! half shares a connector with port N of the other, and the connector stays
+//! powered until both halves have turned the port off.
+//!
+//! Both halves report the same Container ID in their BOS descriptors. Hubs
+//! that don't have one are paired by vendor ID, serial number and where
+//! they're plugged in, which is the same for both halves. Hubs with neither
+//! are never paired, as switching power on the wrong hub would cut off
+//! devices nobody asked about.
+
+use crate::device::{self, UsbDevice};
+use crate::error::Error;
+use crate::topology::{self, Hub};
+
+/// Whether `a` and `b` are the two halves of one USB 3 hub.
+pub fn is_companion<D: UsbDevice>(a: &Hub<D>, b: &Hub<D>) -> bool {
+    let usb2 = match (a.info().is_superspeed(), b.info().is_superspeed()) {
+        (false, true) => a,
+        (true, false) => b,
+        _ => return false,
+    };
+    // The USB 2 half of a USB 3 hub reports USB 2.1, so older hubs can't be
+    // one.
+    if usb2.info().usb_version() < 0x0210 {
+        return false;
+    }
+    match (a.container_id(), b.container_id()) {
+        (Some(a), Some(b)) => a == b,
+        _ => {
+            a.info().vendor_id() == b.info().vendor_id()
+                && a.info().serial_number().is_some()
+                && a.info().serial_number() == b.info().serial_number()
+                && a.info().port_chain() == b.info().port_chain()
+        }
+    }
+}
+
+/// The other half of `hub` among `hubs`, if it has one.
+pub fn companion<'a, D: UsbDevice>(hub: &Hub<D>, hubs: &'a [Hub<D>]) -> Option<&'a Hub<D>> {
+    hubs.iter().find(|other| is_companion(hub, other))
+}
+
+/// Look for the other half of `hub` among the hubs on the system.
+pub async fn find_companion(hub: &Hub) -> Result<Option<Hub>, Error> {
+    let devices = device::list().await?;
+    for (index, info) in topology::hub_infos(&devices).iter().enumerate() {
+        if info.is_superspeed() == hub.info().is_superspeed() {
+            continue;
+        }
+        let other = Hub::describe(index, info, &devices).await;
+        if is_companion(hub, &other) {
+            return Ok(Some(other));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HubControl;
+    use crate::descriptor::PowerSwitching;
+    use crate::sim::{SimDevice, SimHub};
+
+    const CONTAINER_ID: [u8; 16] = [0x5a; 16];
+
+    async fn hub(sim: &SimHub, index: usize) -> Hub<SimDevice> {
+        let control = HubControl::with_transport(sim.clone(), sim.info().is_superspeed());
+        Hub::new(
+            index,
+            sim.info(),
+            control.descriptor().await.ok(),
+            &sim.devices(),
+        )
+        .with_container_id(control.container_id().await.unwrap_or(None))
+    }
+
+    fn usb2(port_chain: &[u8]) -> SimDevice {
+        SimDevice::hub("1", port_chain, 0x0424, 0x2734).with_usb_version(0x0210)
+    }
+
+    fn superspeed(port_chain: &[u8]) -> SimDevice {
+        SimDevice::hub("2", port_chain, 0x0424, 0x5734).with_usb_version(0x0300)
+    }
+
+    #[tokio::test]
+    async fn pairs_by_container_id() {
+        // The halves are on different root ports, so only the Container ID
+        // ties them together.
+        let a =
+            SimHub::new(usb2(&[1]), 4, PowerSwitching::Individual).with_container_id(CONTAINER_ID);
+        let b = SimHub::new(superspeed(&[3]), 4, PowerSwitching::Individual)
+            .with_container_id(CONTAINER_ID);
+        let other = SimHub::new(superspeed(&[1]), 4, PowerSwitching::Individual)
+            .with_container_id([0x11; 16]);
+        let hubs = [hub(&a, 0).await, hub(&b, 1).await, hub(&other, 2).await];
+
+        assert_eq!(companion(&hubs[0], &hubs).map(|hub| hub.index()), Some(1));
+        assert_eq!(companion(&hubs[1], &hubs).map(|hub| hub.index()), Some(0));
+        assert!(companion(&hubs[2], &hubs).is_none());
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_location_and_serial_number() {
+        let a = SimHub::new(
+            usb2(&[2]).with_serial_number("0001"),
+            4,
+            PowerSwitching::Individual,
+        );
+        let b = SimHub::new(
+            superspeed(&[2]).with_serial_number("0001"),
+            4,
+            PowerSwitching::Individual,
+        );
+        let elsewhere = SimHub::new(
+            superspeed(&[3]).with_serial_number("0001"),
+            4,
+            PowerSwitching::Individual,
+        );
+        let old = SimHub::new(
+            SimDevice::hub("3", &[2], 0x0424, 0x2514).with_serial_number("0001"),
+            4,
+            PowerSwitching::Individual,
+        );
+        let hubs = [
+            hub(&a, 0).await,
+            hub(&b, 1).await,
+            hub(&elsewhere, 2).await,
+            hub(&old, 3).await,
+        ];
+
+        assert!(is_companion(&hubs[0], &hubs[1]));
+        assert!(!is_companion(&hubs[0], &hubs[2]));
+        // A USB 2.0 hub isn't half of a USB 3 hub.
+        assert!(!is_companion(&hubs[3], &hubs[1]));
+        assert!(!is_companion(&hubs[0], &hubs[0]));
+    }
+
+    #[tokio::test]
+    async fn hubs_without_serial_numbers_are_not_paired() {
+        let a = SimHub::new(usb2(&[2]), 4, PowerSwitching::Individual);
+        let b = SimHub::new(superspeed(&[2]), 4, PowerSwitching::Individual);
+        let only_one = SimHub::new(
+            superspeed(&[2]).with_serial_number("0001"),
+            4,
+            PowerSwitching::Individual,
+        );
+        let hubs = [hub(&a, 0).await, hub(&b, 1).await, hub(&only_one, 2).await];
+
+        assert!(!is_companion(&hubs[0], &hubs[1]));
+        assert!(!is_companion(&hubs[0], &hubs[2]));
+        assert!(companion(&hubs[0], &hubs).is_none());
+    }
+}
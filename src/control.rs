@@ -0,0 +1,797 @@
+//! Sending hub class requests (USB 2.0 §11.24, USB 3.2 §10.16) to a hub.
+
+use std::time::Duration;
+
+use nusb::transfer::{ControlIn, ControlOut, ControlType, Recipient, TransferError};
+
+use crate::bos::{self, ContainerId};
+use crate::device::{Device, UsbDevice};
+
+use crate::descriptor::{self, AnyHubDescriptor, HubDescriptor, SuperSpeedHubDescriptor};
+use crate::error::{Error, TimeoutError, UnsupportedError};
+use crate::indicator::Indicator;
+use crate::record::{self, Recorder};
+use crate::selector::Location;
+use crate::status::{Change, HubChange, HubStatus, LinkState, PortStatus};
+use crate::transport::{Backend, Transport, UsbHandle, UsbTransport};
+
+pub(crate) enum UsbDescriptorType {
+    Hub = 0x29,
+    SuperSpeedHub = 0x2a,
+}
+
+pub(crate) enum UsbRequest {
+    GetStatus = 0,
+    ClearFeature = 1,
+    SetFeature = 3,
+    GetDescriptor = 6,
+}
+
+/// Port feature selectors for SetFeature and ClearFeature (USB 2.0 Table
+/// 11-17, USB 3.2 Table 10-9).
+pub(crate) mod feature {
+    /// Hub features, sent to the device rather than a port
+    pub const C_HUB_LOCAL_POWER: u16 = 0;
+    pub const C_HUB_OVER_CURRENT: u16 = 1;
+
+    pub const PORT_SUSPEND: u16 = 2;
+    pub const PORT_RESET: u16 = 4;
+    pub const PORT_POWER: u16 = 8;
+    pub const PORT_INDICATOR: u16 = 22;
+    pub const C_PORT_CONNECTION: u16 = 16;
+    pub const C_PORT_ENABLE: u16 = 17;
+    pub const C_PORT_SUSPEND: u16 = 18;
+    pub const C_PORT_OVER_CURRENT: u16 = 19;
+    pub const C_PORT_RESET: u16 = 20;
+
+    /// SuperSpeed only
+    pub const PORT_LINK_STATE: u16 = 5;
+    pub const C_PORT_LINK_STATE: u16 = 25;
+    pub const C_PORT_CONFIG_ERROR: u16 = 26;
+    pub const BH_PORT_RESET: u16 = 28;
+    pub const C_BH_PORT_RESET: u16 = 29;
+}
+
+/// How long a port reset may take. Resets take 10 to 20ms on USB 2 and
+/// warm resets up to 100ms on SuperSpeed, so this leaves plenty of margin.
+pub const RESET_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long a port may take to suspend or resume. Resume signalling lasts
+/// 20ms on USB 2, and a SuperSpeed link should leave U3 well within this.
+pub const SUSPEND_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long a SuperSpeed link may take to reach a requested state.
+pub const LINK_STATE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How often to check whether a port reset or resume has finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// An open hub, ready for class requests. Clones talk to the same hub.
+#[derive(Clone)]
+pub struct HubControl<T = UsbTransport> {
+    transport: T,
+    superspeed: bool,
+    usb_version: u16,
+}
+
+impl HubControl {
+    pub async fn new(device: &Device) -> Result<Self, Error> {
+        log::trace!(
+            "Opening device {:04x}:{:04x}...",
+            device.vendor_id(),
+            device.product_id()
+        );
+        let backend = match device {
+            Device::Usb(device_info) => {
+                let device = device_info.open().await?;
+                #[cfg(windows)]
+                let handle = device.claim_interface(0).await?;
+                #[cfg(not(windows))]
+                let handle = device;
+                Backend::Usb(handle)
+            }
+            Device::Replayed(_, replay) => Backend::Replay(replay.clone()),
+        };
+
+        Ok(HubControl {
+            transport: Recorder::new(backend, record::session())
+                .with_hub(Location::of(device).to_string()),
+            superspeed: device.is_superspeed(),
+            usb_version: device.usb_version(),
+        })
+    }
+}
+
+impl HubControl {
+    /// The handle to the hub, unless it's being replayed. On Windows, this
+    /// is the hub's interface, which can only be claimed once.
+    pub(crate) fn handle(&self) -> Option<&UsbHandle> {
+        match self.transport.inner() {
+            Backend::Usb(handle) => Some(handle),
+            Backend::Replay(_) => None,
+        }
+    }
+}
+
+impl<T: Transport> HubControl<T> {
+    /// Control a hub through something other than nusb, such as a
+    /// [`SimHub`](crate::sim::SimHub).
+    pub fn with_transport(transport: T, superspeed: bool) -> Self {
+        HubControl {
+            transport,
+            superspeed,
+            usb_version: if superspeed { 0x0300 } else { 0x0210 },
+        }
+    }
+
+    /// Set the USB version the hub reports, which decides whether it has a
+    /// BOS descriptor. Hubs made with [`HubControl::with_transport`] are
+    /// taken to be USB 2.1 or 3.0 hubs.
+    pub fn with_usb_version(mut self, usb_version: u16) -> Self {
+        self.usb_version = usb_version;
+        self
+    }
+
+    /// Whether this is a SuperSpeed hub, which changes the layout of its
+    /// descriptor and port status.
+    pub fn is_superspeed(&self) -> bool {
+        self.superspeed
+    }
+
+    pub async fn descriptor(&self) -> Result<AnyHubDescriptor, Error> {
+        let descriptor_type = if self.superspeed {
+            UsbDescriptorType::SuperSpeedHub
+        } else {
+            UsbDescriptorType::Hub
+        };
+        let data = ControlIn {
+            control_type: ControlType::Class,
+            recipient: Recipient::Device,
+            request: UsbRequest::GetDescriptor as _,
+            value: (descriptor_type as u16) << 8,
+            index: 0,
+            length: if self.superspeed {
+                descriptor::SUPERSPEED_HUB_DESCRIPTOR_SIZE
+            } else {
+                descriptor::HUB_DESCRIPTOR_MAX_SIZE
+            },
+        };
+        let response = self
+            .transport
+            .control_in(data, Duration::from_secs(5))
+            .await?;
+        log::trace!("Hub descriptor data: {response:02x?}");
+        Ok(if self.superspeed {
+            AnyHubDescriptor::SuperSpeed(SuperSpeedHubDescriptor::parse(&response)?)
+        } else {
+            AnyHubDescriptor::Usb2(HubDescriptor::parse(&response)?)
+        })
+    }
+
+    /// Read the hub's BOS descriptor. `None` for hubs older than USB 2.01,
+    /// which don't have one.
+    pub async fn bos(&self) -> Result<Option<Vec<u8>>, Error> {
+        if self.usb_version < 0x0201 {
+            return Ok(None);
+        }
+        let request = |length| ControlIn {
+            control_type: ControlType::Standard,
+            recipient: Recipient::Device,
+            request: UsbRequest::GetDescriptor as _,
+            value: u16::from(bos::BOS_DESCRIPTOR_TYPE) << 8,
+            index: 0,
+            length,
+        };
+        let header = self
+            .transport
+            .control_in(request(bos::BOS_HEADER_SIZE), Duration::from_secs(1))
+            .await?;
+        let Some(length) = bos::total_length(&header) else {
+            log::error!("Not a BOS descriptor: {header:02x?}");
+            return Err(Error::Transfer(TransferError::Fault));
+        };
+        let response = self
+            .transport
+            .control_in(request(length), Duration::from_secs(1))
+            .await?;
+        log::trace!("BOS descriptor data: {response:02x?}");
+        Ok(Some(response))
+    }
+
+    /// Read the Container ID from the hub's BOS descriptor, which is the
+    /// same for both halves of a USB 3 hub. `None` if the hub doesn't
+    /// report one.
+    pub async fn container_id(&self) -> Result<Option<ContainerId>, Error> {
+        Ok(self.bos().await?.and_then(|bos| bos::container_id(&bos)))
+    }
+
+    pub async fn status(&self, port: u8) -> Result<PortStatus, Error> {
+        let data = ControlIn {
+            control_type: ControlType::Class,
+            recipient: Recipient::Other,
+            request: UsbRequest::GetStatus as _,
+            value: 0,
+            index: port.into(),
+            length: 4,
+        };
+        let response = self
+            .transport
+            .control_in(data, Duration::from_secs(1))
+            .await?;
+        log::trace!("Port status data: {response:02x?}");
+        PortStatus::from_bytes(&response, self.superspeed).ok_or_else(|| {
+            log::error!("Port status response too short: {response:02x?}");
+            Error::Transfer(TransferError::Fault)
+        })
+    }
+
+    /// Read the status of the hub as a whole.
+    pub async fn hub_status(&self) -> Result<HubStatus, Error> {
+        let data = ControlIn {
+            control_type: ControlType::Class,
+            recipient: Recipient::Device,
+            request: UsbRequest::GetStatus as _,
+            value: 0,
+            index: 0,
+            length: 4,
+        };
+        let response = self
+            .transport
+            .control_in(data, Duration::from_secs(1))
+            .await?;
+        log::trace!("Hub status data: {response:02x?}");
+        HubStatus::from_bytes(&response).ok_or_else(|| {
+            log::error!("Hub status response too short: {response:02x?}");
+            Error::Transfer(TransferError::Fault)
+        })
+    }
+
+    /// Send SetFeature or ClearFeature for a port feature selector.
+    pub(crate) async fn port_feature(
+        &self,
+        port: u8,
+        selector: u16,
+        set: bool,
+    ) -> Result<(), Error> {
+        self.feature_request(Recipient::Other, port.into(), selector, set)
+            .await
+    }
+
+    /// Send SetFeature or ClearFeature with a raw `wIndex`, for selectors
+    /// that take an argument in its upper byte, or to the hub itself.
+    async fn feature_request(
+        &self,
+        recipient: Recipient,
+        index: u16,
+        selector: u16,
+        set: bool,
+    ) -> Result<(), Error> {
+        let data = ControlOut {
+            control_type: ControlType::Class,
+            recipient,
+            request: if set {
+                UsbRequest::SetFeature
+            } else {
+                UsbRequest::ClearFeature
+            } as _,
+            value: selector,
+            index,
+            data: &[],
+        };
+        self.transport
+            .control_out(data, Duration::from_secs(5))
+            .await?;
+        Ok(())
+    }
+
+    /// Turn power to `port` on or off.
+    pub async fn set_power(&self, port: u8, enabled: bool) -> Result<(), Error> {
+        log::trace!("Turning port {}...", if enabled { "on" } else { "off" });
+        self.port_feature(port, feature::PORT_POWER, enabled).await
+    }
+
+    /// Set the colour of the indicator LED of `port`, or hand it back to the
+    /// hub with [`Indicator::Automatic`]. Only USB 2.0 hubs with port
+    /// indicators support this.
+    pub async fn set_indicator(&self, port: u8, indicator: Indicator) -> Result<(), Error> {
+        let index = u16::from(port) | u16::from(indicator.selector()) << 8;
+        self.feature_request(Recipient::Other, index, feature::PORT_INDICATOR, true)
+            .await
+    }
+
+    /// Reset the device on `port`: a bus reset on USB 2 hubs, or a warm
+    /// reset on SuperSpeed hubs. Waits for the hub to report that the reset
+    /// has finished, and returns the port's status afterwards, which shows
+    /// whether the port came back enabled and at what speed.
+    pub async fn reset(&self, port: u8) -> Result<PortStatus, Error> {
+        let selector = if self.superspeed {
+            feature::BH_PORT_RESET
+        } else {
+            feature::PORT_RESET
+        };
+        log::trace!("Resetting port {port}...");
+        self.port_feature(port, selector, true).await?;
+
+        let status = self
+            .wait_for(port, RESET_TIMEOUT, "the reset to finish", |status| {
+                status.reset_changed() || status.bh_reset_changed()
+            })
+            .await?;
+        if status.reset_changed() {
+            self.port_feature(port, feature::C_PORT_RESET, false)
+                .await?;
+        }
+        if status.bh_reset_changed() {
+            self.port_feature(port, feature::C_BH_PORT_RESET, false)
+                .await?;
+        }
+        self.status(port).await
+    }
+
+    /// Suspend the device on `port`, or resume it. USB 2 ports are suspended
+    /// with PORT_SUSPEND, and SuperSpeed ports by moving their link to U3.
+    /// Waits for the port to get there, and returns its status afterwards.
+    ///
+    /// Only an enabled port can be suspended, and only a suspended port
+    /// resumed; other ports don't change, so this times out.
+    pub async fn set_suspended(&self, port: u8, suspended: bool) -> Result<PortStatus, Error> {
+        log::trace!(
+            "{} port {port}...",
+            if suspended { "Suspending" } else { "Resuming" }
+        );
+        if self.superspeed {
+            let target = if suspended {
+                LinkState::U3
+            } else {
+                LinkState::U0
+            };
+            self.link_state_request(port, target).await?;
+        } else {
+            // Clearing PORT_SUSPEND starts resume signalling.
+            self.port_feature(port, feature::PORT_SUSPEND, suspended)
+                .await?;
+        }
+
+        let waiting_for = if suspended {
+            "the port to suspend"
+        } else {
+            "the port to resume"
+        };
+        let status = self
+            .wait_for(port, SUSPEND_TIMEOUT, waiting_for, |status| {
+                status.suspended() == suspended
+            })
+            .await?;
+        // Both hubs flag the end of a resume with a change bit.
+        if status.suspend_changed() {
+            self.port_feature(port, feature::C_PORT_SUSPEND, false)
+                .await?;
+        }
+        if status.link_state_changed() {
+            self.port_feature(port, feature::C_PORT_LINK_STATE, false)
+                .await?;
+        }
+        self.status(port).await
+    }
+
+    /// Move the link of a SuperSpeed port to `state`, waiting for it to get
+    /// there, and return the port's status afterwards.
+    ///
+    /// [`LinkState::Disabled`] disables the port, which cuts the device off
+    /// without switching power, and is undone with [`LinkState::RxDetect`].
+    /// The port then trains a link with whatever is attached, so it's done
+    /// once it leaves SS.Disabled. Hubs only accept U0 to U3, SS.Disabled
+    /// and Rx.Detect, and only the transitions the link allows.
+    pub async fn set_link_state(&self, port: u8, state: LinkState) -> Result<PortStatus, Error> {
+        if !self.superspeed {
+            return Err(UnsupportedError {
+                what: "link states, which only SuperSpeed hubs have",
+            }
+            .into());
+        }
+        log::trace!("Moving the link of port {port} to {state}...");
+        self.link_state_request(port, state).await?;
+        let status = self
+            .wait_for(
+                port,
+                LINK_STATE_TIMEOUT,
+                "the link state to change",
+                |status| match state {
+                    LinkState::RxDetect => status.link_state() != Some(LinkState::Disabled),
+                    state => status.link_state() == Some(state),
+                },
+            )
+            .await?;
+        if status.link_state_changed() {
+            self.port_feature(port, feature::C_PORT_LINK_STATE, false)
+                .await?;
+        }
+        self.status(port).await
+    }
+
+    /// Send SetFeature(PORT_LINK_STATE), which takes the state in the upper
+    /// byte of `wIndex`.
+    async fn link_state_request(&self, port: u8, state: LinkState) -> Result<(), Error> {
+        let index = u16::from(port) | u16::from(u8::from(state)) << 8;
+        self.feature_request(Recipient::Other, index, feature::PORT_LINK_STATE, true)
+            .await
+    }
+
+    pub async fn suspend(&self, port: u8) -> Result<PortStatus, Error> {
+        self.set_suspended(port, true).await
+    }
+
+    pub async fn resume(&self, port: u8) -> Result<PortStatus, Error> {
+        self.set_suspended(port, false).await
+    }
+
+    /// Read the status of `port` until `done` accepts it, giving up after
+    /// `timeout`.
+    async fn wait_for(
+        &self,
+        port: u8,
+        timeout: Duration,
+        waiting_for: &'static str,
+        done: impl Fn(&PortStatus) -> bool,
+    ) -> Result<PortStatus, Error> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let status = self.status(port).await?;
+            if done(&status) {
+                return Ok(status);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(TimeoutError {
+                    port,
+                    timeout,
+                    waiting_for,
+                }
+                .into());
+            }
+        }
+    }
+
+    /// Acknowledge change bits of `port` with ClearFeature, so that they
+    /// read as unchanged until the next change and the hub stops reporting
+    /// the port on its status change endpoint. Bits this kind of hub
+    /// doesn't have are skipped.
+    ///
+    /// The OS hub driver relies on the change bits too, so acknowledging
+    /// them behind its back can hide a change from it.
+    pub async fn acknowledge(&self, port: u8, changes: &[Change]) -> Result<(), Error> {
+        for change in changes {
+            if !change.applies_to(self.superspeed) {
+                continue;
+            }
+            let selector = match change {
+                Change::Connection => feature::C_PORT_CONNECTION,
+                Change::Enable => feature::C_PORT_ENABLE,
+                Change::Suspend => feature::C_PORT_SUSPEND,
+                Change::OverCurrent => feature::C_PORT_OVER_CURRENT,
+                Change::Reset => feature::C_PORT_RESET,
+                Change::BhReset => feature::C_BH_PORT_RESET,
+                Change::LinkState => feature::C_PORT_LINK_STATE,
+                Change::ConfigError => feature::C_PORT_CONFIG_ERROR,
+            };
+            self.port_feature(port, selector, false).await?;
+        }
+        Ok(())
+    }
+
+    /// Clear the given change bits of the hub itself, so that the hub stops
+    /// reporting them on its status change endpoint.
+    pub async fn acknowledge_hub(&self, changes: &[HubChange]) -> Result<(), Error> {
+        for change in changes {
+            let selector = match change {
+                HubChange::LocalPower => feature::C_HUB_LOCAL_POWER,
+                HubChange::OverCurrent => feature::C_HUB_OVER_CURRENT,
+            };
+            self.feature_request(Recipient::Device, 0, selector, false)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn off(&self, port: u8) -> Result<(), Error> {
+        self.set_power(port, false).await
+    }
+
+    pub async fn on(&self, port: u8) -> Result<(), Error> {
+        self.set_power(port, true).await
+    }
+
+    /// Invert the power state of `port`, returning the state it was set to.
+    pub async fn toggle(&self, port: u8) -> Result<bool, Error> {
+        let enabled = !self.status(port).await?.powered();
+        self.set_power(port, enabled).await?;
+        Ok(enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::PowerSwitching;
+    use crate::sim::{SimDevice, SimHub};
+
+    fn hub(switching: PowerSwitching) -> (SimHub, HubControl<SimHub>) {
+        let hub = SimHub::test_hub(4).with_power_switching(switching);
+        let control = HubControl::with_transport(hub.clone(), false);
+        (hub, control)
+    }
+
+    fn superspeed_hub() -> (SimHub, HubControl<SimHub>) {
+        let hub = SimHub::test_superspeed_hub(2);
+        let control = HubControl::with_transport(hub.clone(), true);
+        (hub, control)
+    }
+
+    #[tokio::test]
+    async fn reads_usb2_descriptor() {
+        let (_, control) = hub(PowerSwitching::Individual);
+        let descriptor = control.descriptor().await.unwrap();
+        assert!(matches!(descriptor, AnyHubDescriptor::Usb2(_)));
+        assert_eq!(descriptor.port_count(), 4);
+        assert_eq!(
+            descriptor.characteristics().power_switching(),
+            PowerSwitching::Individual
+        );
+        assert_eq!(descriptor.power_good_delay(), Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn reads_superspeed_descriptor() {
+        let (_, control) = superspeed_hub();
+        let descriptor = control.descriptor().await.unwrap();
+        assert!(matches!(descriptor, AnyHubDescriptor::SuperSpeed(_)));
+        assert_eq!(descriptor.port_count(), 2);
+        assert_eq!(descriptor.controller_current_ma(), 100);
+    }
+
+    #[tokio::test]
+    async fn status_follows_attached_device() {
+        let (hub, control) = hub(PowerSwitching::Individual);
+        let status = control.status(1).await.unwrap();
+        assert!(status.powered());
+        assert!(!status.connected());
+        assert_eq!(status.speed(), None);
+
+        hub.attach(1, SimDevice::new(0x1366, 0x0105));
+        let status = control.status(1).await.unwrap();
+        assert!(status.connected());
+        assert!(status.enabled());
+        assert_eq!(status.speed(), Some(crate::status::PortSpeed::High));
+        assert_eq!(status.changes(), ["C_PORT_CONNECTION"]);
+    }
+
+    #[tokio::test]
+    async fn superspeed_status_uses_superspeed_layout() {
+        let (hub, control) = superspeed_hub();
+        hub.attach(2, SimDevice::new(0x0781, 0x5581).with_usb_version(0x0320));
+
+        let status = control.status(2).await.unwrap();
+        assert!(status.powered());
+        assert_eq!(status.link_state(), Some(crate::status::LinkState::U0));
+        assert_eq!(
+            status.speed(),
+            Some(crate::status::PortSpeed::SuperSpeed(0))
+        );
+
+        control.off(1).await.unwrap();
+        let status = control.status(1).await.unwrap();
+        assert!(!status.powered());
+        assert_eq!(
+            status.link_state(),
+            Some(crate::status::LinkState::Disabled)
+        );
+    }
+
+    #[tokio::test]
+    async fn toggle_switches_one_port() {
+        let (hub, control) = hub(PowerSwitching::Individual);
+        hub.attach(2, SimDevice::new(0x1366, 0x0105));
+
+        assert!(!control.toggle(2).await.unwrap());
+        assert!(!hub.is_powered(2));
+        assert!(hub.is_powered(1));
+        let status = control.status(2).await.unwrap();
+        assert!(!status.powered());
+        assert!(!status.connected());
+        assert_eq!(hub.devices().len(), 1);
+
+        assert!(control.toggle(2).await.unwrap());
+        assert!(control.status(2).await.unwrap().connected());
+        assert_eq!(hub.devices().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn ganged_hub_switches_every_port() {
+        let (hub, control) = hub(PowerSwitching::Ganged);
+        control.off(3).await.unwrap();
+        assert!((1..=4).all(|port| !hub.is_powered(port)));
+        control.on(1).await.unwrap();
+        assert!((1..=4).all(|port| hub.is_powered(port)));
+    }
+
+    #[tokio::test]
+    async fn hub_without_switching_ignores_power_requests() {
+        let (hub, control) = hub(PowerSwitching::NoSwitching);
+        control.off(1).await.unwrap();
+        assert!(hub.is_powered(1));
+        assert!(control.status(1).await.unwrap().powered());
+    }
+
+    #[tokio::test]
+    async fn reset_waits_for_the_port_to_come_back() {
+        let (hub, control) = hub(PowerSwitching::Individual);
+        hub.attach(3, SimDevice::new(0x1366, 0x0105));
+        let status = control.reset(3).await.unwrap();
+        assert!(status.enabled());
+        assert_eq!(status.speed(), Some(crate::status::PortSpeed::High));
+        assert!(!status.reset_changed());
+        assert_eq!(status.changes(), ["C_PORT_CONNECTION"]);
+    }
+
+    #[tokio::test]
+    async fn superspeed_reset_is_a_warm_reset() {
+        let (_, control) = superspeed_hub();
+        let status = control.reset(1).await.unwrap();
+        assert!(!status.reset_changed());
+        assert!(!status.bh_reset_changed());
+        assert!(!status.enabled());
+    }
+
+    #[tokio::test]
+    async fn reset_of_an_unpowered_port_times_out() {
+        let (_, control) = hub(PowerSwitching::Individual);
+        control.off(2).await.unwrap();
+        assert!(matches!(
+            control.reset(2).await,
+            Err(Error::Timeout(TimeoutError { port: 2, .. }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn suspend_and_resume_a_usb2_port() {
+        let (hub, control) = hub(PowerSwitching::Individual);
+        hub.attach(1, SimDevice::new(0x1366, 0x0105));
+        let status = control.suspend(1).await.unwrap();
+        assert!(status.suspended());
+        assert_eq!(
+            status.to_string(),
+            "ON, connected, high-speed, enabled, suspended (changed since last acknowledged: connection)"
+        );
+
+        let status = control.resume(1).await.unwrap();
+        assert!(!status.suspended());
+        assert!(!status.suspend_changed());
+        assert!(status.enabled());
+    }
+
+    #[tokio::test]
+    async fn suspend_and_resume_a_superspeed_port() {
+        let (hub, control) = superspeed_hub();
+        hub.attach(2, SimDevice::new(0x0781, 0x5581).with_usb_version(0x0320));
+
+        let status = control.suspend(2).await.unwrap();
+        assert_eq!(status.link_state(), Some(crate::status::LinkState::U3));
+        assert!(status.to_string().contains(", U3 (suspended)"));
+
+        let status = control.resume(2).await.unwrap();
+        assert_eq!(status.link_state(), Some(crate::status::LinkState::U0));
+        assert!(!status.link_state_changed());
+    }
+
+    #[tokio::test]
+    async fn suspending_an_empty_port_times_out() {
+        let (_, control) = hub(PowerSwitching::Individual);
+        assert!(matches!(
+            control.suspend(4).await,
+            Err(Error::Timeout(TimeoutError { port: 4, .. }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn acknowledge_clears_only_the_requested_bits() {
+        let (hub, control) = hub(PowerSwitching::Individual);
+        hub.attach(2, SimDevice::new(0x1366, 0x0105));
+        hub.set_over_current(2, true);
+        assert_eq!(
+            control.status(2).await.unwrap().changed(),
+            [Change::Connection, Change::OverCurrent]
+        );
+
+        // Link state changes don't exist on USB 2 hubs, so they're skipped.
+        control
+            .acknowledge(2, &[Change::Connection, Change::LinkState])
+            .await
+            .unwrap();
+        let status = control.status(2).await.unwrap();
+        assert_eq!(status.changed(), [Change::OverCurrent]);
+        assert_eq!(
+            status.to_string(),
+            "off, OVER-CURRENT (changed since last acknowledged: over-current)"
+        );
+
+        control.acknowledge(2, &Change::ALL).await.unwrap();
+        assert!(control.status(2).await.unwrap().changed().is_empty());
+    }
+
+    #[tokio::test]
+    async fn hub_status_reports_lost_local_power() {
+        let (hub, control) = hub(PowerSwitching::Individual);
+        let status = control.hub_status().await.unwrap();
+        assert_eq!(status.to_string(), "local power good");
+        assert!(status.changes().is_empty());
+
+        hub.set_local_power_lost(true);
+        let status = control.hub_status().await.unwrap();
+        assert!(status.local_power_lost());
+        assert!(!status.over_current());
+        assert_eq!(status.changes(), ["C_HUB_LOCAL_POWER"]);
+        assert_eq!(
+            status.to_string(),
+            "local power LOST (changed since last acknowledged: local-power)"
+        );
+
+        hub.set_hub_over_current(true);
+        control
+            .acknowledge_hub(&[HubChange::LocalPower])
+            .await
+            .unwrap();
+        let status = control.hub_status().await.unwrap();
+        assert!(status.local_power_lost());
+        assert_eq!(status.changed(), [HubChange::OverCurrent]);
+    }
+
+    #[tokio::test]
+    async fn superspeed_port_can_be_disabled_and_enabled_again() {
+        let (hub, control) = superspeed_hub();
+        hub.attach(1, SimDevice::new(0x0781, 0x5581).with_usb_version(0x0320));
+
+        let status = control
+            .set_link_state(1, LinkState::Disabled)
+            .await
+            .unwrap();
+        assert_eq!(status.link_state(), Some(LinkState::Disabled));
+        assert!(status.powered());
+        assert!(!status.connected());
+        assert_eq!(hub.devices().len(), 1);
+
+        let status = control
+            .set_link_state(1, LinkState::RxDetect)
+            .await
+            .unwrap();
+        assert_eq!(status.link_state(), Some(LinkState::U0));
+        assert!(status.connected());
+        assert_eq!(hub.devices().len(), 2);
+
+        assert!(matches!(
+            control.set_link_state(1, LinkState::Loopback).await,
+            Err(Error::Transfer(TransferError::Stall))
+        ));
+    }
+
+    #[tokio::test]
+    async fn usb2_hub_has_no_link_states() {
+        let (_, control) = hub(PowerSwitching::Individual);
+        assert!(matches!(
+            control.set_link_state(1, LinkState::Disabled).await,
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_port_stalls() {
+        let (_, control) = hub(PowerSwitching::Individual);
+        assert!(matches!(
+            control.status(5).await,
+            Err(Error::Transfer(TransferError::Stall))
+        ));
+        assert!(matches!(
+            control.on(0).await,
+            Err(Error::Transfer(TransferError::Stall))
+        ));
+    }
+}
@@ -0,0 +1,414 @@
+//! Parsing of the hub class descriptor (USB 2.0 §11.23.2.1) and the
+//! SuperSpeed hub descriptor (USB 3.2 §10.15.2.1).
+
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Offset of `DeviceRemovable` in a USB 2.0 hub descriptor.
+const DEVICE_REMOVABLE_OFFSET: usize = 7;
+
+/// A USB 2.0 hub descriptor may hold up to 255 ports, which requires 32
+/// bytes for each of `DeviceRemovable` and `PortPwrCtrlMask`.
+pub const HUB_DESCRIPTOR_MAX_SIZE: u16 = 7 + 32 + 32;
+
+/// SuperSpeed hub descriptors are always 12 bytes long.
+pub const SUPERSPEED_HUB_DESCRIPTOR_SIZE: u16 = 12;
+
+#[derive(Debug)]
+pub enum DescriptorError {
+    TooShort { expected: usize, actual: usize },
+    WrongType(u8),
+}
+
+impl core::fmt::Display for DescriptorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DescriptorError::TooShort { expected, actual } => {
+                write!(
+                    f,
+                    "hub descriptor is {actual} bytes, expected at least {expected}"
+                )
+            }
+            DescriptorError::WrongType(kind) => {
+                write!(f, "unexpected descriptor type {kind:#04x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DescriptorError {}
+
+/// How the hub switches power to its ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerSwitching {
+    /// All ports are powered on and off together.
+    Ganged,
+    /// Each port can be powered on and off on its own.
+    Individual,
+    /// Ports are always powered whenever the hub is.
+    NoSwitching,
+}
+
+impl core::fmt::Display for PowerSwitching {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PowerSwitching::Ganged => write!(f, "ganged"),
+            PowerSwitching::Individual => write!(f, "per-port"),
+            PowerSwitching::NoSwitching => write!(f, "no switching"),
+        }
+    }
+}
+
+/// How the hub reports over-current conditions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverCurrentProtection {
+    /// Over-current is reported for the hub as a whole.
+    Global,
+    /// Over-current is reported on each port.
+    Individual,
+    NoProtection,
+}
+
+impl core::fmt::Display for OverCurrentProtection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OverCurrentProtection::Global => write!(f, "global"),
+            OverCurrentProtection::Individual => write!(f, "per-port"),
+            OverCurrentProtection::NoProtection => write!(f, "no"),
+        }
+    }
+}
+
+/// The `wHubCharacteristics` field of a hub descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HubCharacteristics(u16);
+
+impl HubCharacteristics {
+    pub fn power_switching(&self) -> PowerSwitching {
+        match self.0 & 0b11 {
+            0b00 => PowerSwitching::Ganged,
+            0b01 => PowerSwitching::Individual,
+            _ => PowerSwitching::NoSwitching,
+        }
+    }
+
+    /// Whether the hub is part of a compound device.
+    pub fn compound_device(&self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+
+    pub fn over_current_protection(&self) -> OverCurrentProtection {
+        match (self.0 >> 3) & 0b11 {
+            0b00 => OverCurrentProtection::Global,
+            0b01 => OverCurrentProtection::Individual,
+            _ => OverCurrentProtection::NoProtection,
+        }
+    }
+
+    /// The number of full-speed bit times the Transaction Translator needs
+    /// between transactions. Only meaningful for USB 2.0 hubs.
+    pub fn tt_think_time(&self) -> u8 {
+        (((self.0 >> 5) & 0b11) as u8 + 1) * 8
+    }
+
+    /// Whether the ports have software-controllable indicator LEDs. Only
+    /// meaningful for USB 2.0 hubs.
+    pub fn port_indicators(&self) -> bool {
+        self.0 & (1 << 7) != 0
+    }
+}
+
+/// A USB 2.0 hub descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HubDescriptor {
+    pub port_count: u8,
+    pub characteristics: HubCharacteristics,
+    /// Time from power-on to power-good, in units of 2 ms.
+    pub power_on_to_power_good: u8,
+    /// Maximum current used by the hub controller, in mA.
+    pub controller_current: u8,
+    /// One bit per port, starting with bit 1. A set bit means the device
+    /// attached to that port is not removable.
+    pub device_removable: Vec<u8>,
+}
+
+impl HubDescriptor {
+    pub fn parse(data: &[u8]) -> Result<Self, DescriptorError> {
+        check_header(data, 0x29, DEVICE_REMOVABLE_OFFSET)?;
+        let port_count = data[2];
+        let removable_len = (port_count as usize + 1).div_ceil(8);
+        let expected = DEVICE_REMOVABLE_OFFSET + removable_len;
+        if data.len() < expected {
+            return Err(DescriptorError::TooShort {
+                expected,
+                actual: data.len(),
+            });
+        }
+        Ok(HubDescriptor {
+            port_count,
+            characteristics: HubCharacteristics(u16::from_le_bytes([data[3], data[4]])),
+            power_on_to_power_good: data[5],
+            controller_current: data[6],
+            device_removable: data[DEVICE_REMOVABLE_OFFSET..expected].to_vec(),
+        })
+    }
+
+    pub fn removable(&self, port: u8) -> bool {
+        self.device_removable
+            .get(port as usize / 8)
+            .is_none_or(|bits| bits & (1 << (port % 8)) == 0)
+    }
+}
+
+/// A SuperSpeed hub descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuperSpeedHubDescriptor {
+    pub port_count: u8,
+    pub characteristics: HubCharacteristics,
+    /// Time from power-on to power-good, in units of 2 ms.
+    pub power_on_to_power_good: u8,
+    /// Maximum current used by the hub controller, in units of 4 mA.
+    pub controller_current: u8,
+    /// Packet header decode latency, in units of 0.1 µs.
+    pub header_decode_latency: u8,
+    /// Average delay introduced by the hub, in ns.
+    pub hub_delay: u16,
+    /// One bit per port, starting with bit 1. A set bit means the device
+    /// attached to that port is not removable.
+    pub device_removable: u16,
+}
+
+impl SuperSpeedHubDescriptor {
+    pub fn parse(data: &[u8]) -> Result<Self, DescriptorError> {
+        check_header(data, 0x2a, SUPERSPEED_HUB_DESCRIPTOR_SIZE as usize)?;
+        Ok(SuperSpeedHubDescriptor {
+            port_count: data[2],
+            characteristics: HubCharacteristics(u16::from_le_bytes([data[3], data[4]])),
+            power_on_to_power_good: data[5],
+            controller_current: data[6],
+            header_decode_latency: data[7],
+            hub_delay: u16::from_le_bytes([data[8], data[9]]),
+            device_removable: u16::from_le_bytes([data[10], data[11]]),
+        })
+    }
+
+    pub fn removable(&self, port: u8) -> bool {
+        port >= 16 || self.device_removable & (1 << port) == 0
+    }
+}
+
+fn check_header(data: &[u8], kind: u8, expected: usize) -> Result<(), DescriptorError> {
+    if data.len() < expected {
+        return Err(DescriptorError::TooShort {
+            expected,
+            actual: data.len(),
+        });
+    }
+    if data[1] != kind {
+        return Err(DescriptorError::WrongType(data[1]));
+    }
+    Ok(())
+}
+
+/// The descriptor of either a USB 2.0 or a SuperSpeed hub.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnyHubDescriptor {
+    Usb2(HubDescriptor),
+    SuperSpeed(SuperSpeedHubDescriptor),
+}
+
+impl AnyHubDescriptor {
+    pub fn port_count(&self) -> u8 {
+        match self {
+            AnyHubDescriptor::Usb2(d) => d.port_count,
+            AnyHubDescriptor::SuperSpeed(d) => d.port_count,
+        }
+    }
+
+    pub fn characteristics(&self) -> HubCharacteristics {
+        match self {
+            AnyHubDescriptor::Usb2(d) => d.characteristics,
+            AnyHubDescriptor::SuperSpeed(d) => d.characteristics,
+        }
+    }
+
+    /// How long to wait after powering a port before its power is good.
+    pub fn power_good_delay(&self) -> Duration {
+        let units = match self {
+            AnyHubDescriptor::Usb2(d) => d.power_on_to_power_good,
+            AnyHubDescriptor::SuperSpeed(d) => d.power_on_to_power_good,
+        };
+        Duration::from_millis(units as u64 * 2)
+    }
+
+    /// Maximum current used by the hub controller, in mA.
+    pub fn controller_current_ma(&self) -> u16 {
+        match self {
+            AnyHubDescriptor::Usb2(d) => d.controller_current as u16,
+            AnyHubDescriptor::SuperSpeed(d) => d.controller_current as u16 * 4,
+        }
+    }
+
+    /// Whether the host can drive the port indicator LEDs, which only USB
+    /// 2.0 hubs have.
+    pub fn port_indicators(&self) -> bool {
+        matches!(self, AnyHubDescriptor::Usb2(_)) && self.characteristics().port_indicators()
+    }
+
+    pub fn removable(&self, port: u8) -> bool {
+        match self {
+            AnyHubDescriptor::Usb2(d) => d.removable(port),
+            AnyHubDescriptor::SuperSpeed(d) => d.removable(port),
+        }
+    }
+}
+
+impl core::fmt::Display for AnyHubDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let characteristics = self.characteristics();
+        write!(
+            f,
+            "{} ports, {} power switching, {} over-current protection, power good after {} ms, {} mA",
+            self.port_count(),
+            characteristics.power_switching(),
+            characteristics.over_current_protection(),
+            self.power_good_delay().as_millis(),
+            self.controller_current_ma(),
+        )?;
+        if characteristics.compound_device() {
+            write!(f, ", compound device")?;
+        }
+        match self {
+            AnyHubDescriptor::Usb2(_) => {
+                write!(
+                    f,
+                    ", TT think time {} FS bit times",
+                    characteristics.tt_think_time()
+                )?;
+                if characteristics.port_indicators() {
+                    write!(f, ", port indicators")?;
+                }
+            }
+            AnyHubDescriptor::SuperSpeed(d) => {
+                write!(
+                    f,
+                    ", header decode latency {}.{} µs, hub delay {} ns",
+                    d.header_decode_latency / 10,
+                    d.header_decode_latency % 10,
+                    d.hub_delay
+                )?;
+            }
+        }
+        let fixed: Vec<String> = (1..=self.port_count())
+            .filter(|port| !self.removable(*port))
+            .map(|port| port.to_string())
+            .collect();
+        if !fixed.is_empty() {
+            write!(f, ", non-removable devices on ports {}", fixed.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 10-port hub with per-port power switching and over-current
+    /// protection and port indicators, whose devices on ports 1 and 9 are
+    /// built in. DeviceRemovable and PortPwrCtrlMask take two bytes each.
+    const TEN_PORTS: [u8; 11] = [11, 0x29, 10, 0x89, 0x00, 50, 100, 0x02, 0x02, 0xff, 0xff];
+
+    /// A 4-port SuperSpeed hub whose device on port 2 is built in.
+    const SUPERSPEED: [u8; 12] = [
+        12, 0x2a, 4, 0x09, 0x00, 50, 25, 0x10, 0x34, 0x12, 0x04, 0x00,
+    ];
+
+    #[test]
+    fn parses_long_removable_bitmap() {
+        let descriptor = HubDescriptor::parse(&TEN_PORTS).unwrap();
+        assert_eq!(descriptor.port_count, 10);
+        assert_eq!(descriptor.device_removable, [0x02, 0x02]);
+        for (port, removable) in [(1, false), (2, true), (8, true), (9, false), (10, true)] {
+            assert_eq!(descriptor.removable(port), removable, "port {port}");
+        }
+        // Ports past the end of the bitmap don't exist, so nothing fixed is
+        // attached to them.
+        assert!(descriptor.removable(200));
+
+        let characteristics = descriptor.characteristics;
+        assert_eq!(
+            characteristics.power_switching(),
+            PowerSwitching::Individual
+        );
+        assert_eq!(
+            characteristics.over_current_protection(),
+            OverCurrentProtection::Individual
+        );
+        assert!(characteristics.port_indicators());
+        assert!(!characteristics.compound_device());
+        assert_eq!(characteristics.tt_think_time(), 8);
+
+        let descriptor = AnyHubDescriptor::Usb2(descriptor);
+        assert_eq!(descriptor.power_good_delay(), Duration::from_millis(100));
+        assert_eq!(descriptor.controller_current_ma(), 100);
+        assert!(descriptor.port_indicators());
+    }
+
+    #[test]
+    fn parses_superspeed_layout() {
+        let descriptor = SuperSpeedHubDescriptor::parse(&SUPERSPEED).unwrap();
+        assert_eq!(descriptor.port_count, 4);
+        assert_eq!(descriptor.header_decode_latency, 0x10);
+        assert_eq!(descriptor.hub_delay, 0x1234);
+        assert_eq!(descriptor.device_removable, 0x0004);
+        assert!(descriptor.removable(1));
+        assert!(!descriptor.removable(2));
+        assert!(descriptor.removable(16));
+
+        let descriptor = AnyHubDescriptor::SuperSpeed(descriptor);
+        assert_eq!(descriptor.power_good_delay(), Duration::from_millis(100));
+        // Counted in units of 4 mA rather than 1 mA.
+        assert_eq!(descriptor.controller_current_ma(), 100);
+        // The bit means something else on SuperSpeed hubs.
+        assert!(!descriptor.port_indicators());
+    }
+
+    #[test]
+    fn rejects_short_descriptors() {
+        for (data, expected) in [
+            (&TEN_PORTS[..6], 7),
+            // Too short for the two bytes of DeviceRemovable.
+            (&TEN_PORTS[..8], 9),
+        ] {
+            assert!(matches!(
+                HubDescriptor::parse(data),
+                Err(DescriptorError::TooShort { expected: e, actual }) if e == expected && actual == data.len()
+            ));
+        }
+        assert!(matches!(
+            SuperSpeedHubDescriptor::parse(&SUPERSPEED[..11]),
+            Err(DescriptorError::TooShort {
+                expected: 12,
+                actual: 11
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_wrong_descriptor_type() {
+        assert!(matches!(
+            HubDescriptor::parse(&SUPERSPEED),
+            Err(DescriptorError::WrongType(0x2a))
+        ));
+        let mut ten_ports = [0; 12];
+        ten_ports[..11].copy_from_slice(&TEN_PORTS);
+        assert!(matches!(
+            SuperSpeedHubDescriptor::parse(&ten_ports),
+            Err(DescriptorError::WrongType(0x29))
+        ));
+    }
+}
@@ -0,0 +1,164 @@
+//! What hubctl needs to know about a USB device to place it in the topology.
+
+use nusb::DeviceInfo;
+
+use crate::error::Error;
+use crate::record::{self, Replay};
+use crate::sim::SimDevice;
+
+/// The properties of a USB device used to find hubs and the devices attached
+/// to them. Implemented for [`nusb::DeviceInfo`], for the simulated devices
+/// in [`sim`](crate::sim), and for [`Device`], which is either.
+pub trait UsbDevice: Clone {
+    fn bus_id(&self) -> &str;
+    fn port_chain(&self) -> &[u8];
+    fn vendor_id(&self) -> u16;
+    fn product_id(&self) -> u16;
+    fn class(&self) -> u8;
+    /// The USB version from the device descriptor, such as `0x0210`.
+    fn usb_version(&self) -> u16;
+    fn manufacturer_string(&self) -> Option<&str>;
+    fn product_string(&self) -> Option<&str>;
+    fn serial_number(&self) -> Option<&str>;
+
+    /// Whether this is a SuperSpeed device, or the SuperSpeed half of a hub.
+    fn is_superspeed(&self) -> bool {
+        self.usb_version() >= 0x0300
+    }
+}
+
+impl UsbDevice for nusb::DeviceInfo {
+    fn bus_id(&self) -> &str {
+        nusb::DeviceInfo::bus_id(self)
+    }
+
+    fn port_chain(&self) -> &[u8] {
+        nusb::DeviceInfo::port_chain(self)
+    }
+
+    fn vendor_id(&self) -> u16 {
+        nusb::DeviceInfo::vendor_id(self)
+    }
+
+    fn product_id(&self) -> u16 {
+        nusb::DeviceInfo::product_id(self)
+    }
+
+    fn class(&self) -> u8 {
+        nusb::DeviceInfo::class(self)
+    }
+
+    fn usb_version(&self) -> u16 {
+        nusb::DeviceInfo::usb_version(self)
+    }
+
+    fn manufacturer_string(&self) -> Option<&str> {
+        nusb::DeviceInfo::manufacturer_string(self)
+    }
+
+    fn product_string(&self) -> Option<&str> {
+        nusb::DeviceInfo::product_string(self)
+    }
+
+    fn serial_number(&self) -> Option<&str> {
+        nusb::DeviceInfo::serial_number(self)
+    }
+}
+
+/// A device found by [`list`]: either one attached to this machine, or one
+/// from a recorded session along with the replay of the transfers sent to it.
+#[derive(Debug, Clone)]
+pub enum Device {
+    Usb(DeviceInfo),
+    Replayed(SimDevice, Replay),
+}
+
+impl Device {
+    /// The device as nusb sees it, unless it's being replayed.
+    pub fn info(&self) -> Option<&DeviceInfo> {
+        match self {
+            Device::Usb(info) => Some(info),
+            Device::Replayed(..) => None,
+        }
+    }
+}
+
+impl UsbDevice for Device {
+    fn bus_id(&self) -> &str {
+        match self {
+            Device::Usb(info) => info.bus_id(),
+            Device::Replayed(device, _) => device.bus_id(),
+        }
+    }
+
+    fn port_chain(&self) -> &[u8] {
+        match self {
+            Device::Usb(info) => info.port_chain(),
+            Device::Replayed(device, _) => device.port_chain(),
+        }
+    }
+
+    fn vendor_id(&self) -> u16 {
+        match self {
+            Device::Usb(info) => info.vendor_id(),
+            Device::Replayed(device, _) => device.vendor_id(),
+        }
+    }
+
+    fn product_id(&self) -> u16 {
+        match self {
+            Device::Usb(info) => info.product_id(),
+            Device::Replayed(device, _) => device.product_id(),
+        }
+    }
+
+    fn class(&self) -> u8 {
+        match self {
+            Device::Usb(info) => info.class(),
+            Device::Replayed(device, _) => device.class(),
+        }
+    }
+
+    fn usb_version(&self) -> u16 {
+        match self {
+            Device::Usb(info) => info.usb_version(),
+            Device::Replayed(device, _) => device.usb_version(),
+        }
+    }
+
+    fn manufacturer_string(&self) -> Option<&str> {
+        match self {
+            Device::Usb(info) => info.manufacturer_string(),
+            Device::Replayed(device, _) => device.manufacturer_string(),
+        }
+    }
+
+    fn product_string(&self) -> Option<&str> {
+        match self {
+            Device::Usb(info) => info.product_string(),
+            Device::Replayed(device, _) => device.product_string(),
+        }
+    }
+
+    fn serial_number(&self) -> Option<&str> {
+        match self {
+            Device::Usb(info) => info.serial_number(),
+            Device::Replayed(device, _) => device.serial_number(),
+        }
+    }
+}
+
+/// List the devices attached to this machine, or while a session is being
+/// replayed, the devices that were listed at the same point of the
+/// recording. While a session is being recorded, the list goes into the
+/// recording too.
+pub async fn list() -> Result<Vec<Device>, Error> {
+    if let Some(replay) = record::replay() {
+        return Ok(replay.devices());
+    }
+    let devices: Vec<DeviceInfo> = nusb::list_devices().await?.collect();
+    if let Some(recording) = record::session() {
+        recording.push_devices(devices.iter().map(SimDevice::of).collect());
+    }
+    Ok(devices.into_iter().map(Device::Usb).collect())
+}
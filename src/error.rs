@@ -0,0 +1,166 @@
+//! Errors returned by the library.
+
+use std::time::Duration;
+
+use nusb::transfer::TransferError;
+
+use crate::descriptor::DescriptorError;
+use crate::selector::LookupError;
+
+/// Anything that can go wrong while talking to a hub.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Devices couldn't be listed, or the hub couldn't be opened.
+    Usb(nusb::Error),
+    /// The hub rejected a request, or didn't respond to it.
+    Transfer(TransferError),
+    /// The hub sent back a descriptor that couldn't be parsed.
+    Descriptor(DescriptorError),
+    /// The requested hub or port doesn't exist, or is ambiguous.
+    Lookup(LookupError),
+    /// The hub accepted a power change but didn't act on it.
+    Ignored(IgnoredError),
+    /// A port didn't get to the expected state in time.
+    Timeout(TimeoutError),
+    /// A port or the whole hub is reporting over-current.
+    OverCurrent(OverCurrentError),
+    /// The request doesn't apply to this kind of hub.
+    Unsupported(UnsupportedError),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Usb(e) => write!(f, "{e}"),
+            Error::Transfer(e) => write!(f, "{e}"),
+            Error::Descriptor(e) => write!(f, "{e}"),
+            Error::Lookup(e) => write!(f, "{e}"),
+            Error::Ignored(e) => write!(f, "{e}"),
+            Error::Timeout(e) => write!(f, "{e}"),
+            Error::OverCurrent(e) => write!(f, "{e}"),
+            Error::Unsupported(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<nusb::Error> for Error {
+    fn from(value: nusb::Error) -> Self {
+        Error::Usb(value)
+    }
+}
+
+impl From<TransferError> for Error {
+    fn from(value: TransferError) -> Self {
+        Error::Transfer(value)
+    }
+}
+
+impl From<DescriptorError> for Error {
+    fn from(value: DescriptorError) -> Self {
+        Error::Descriptor(value)
+    }
+}
+
+impl From<LookupError> for Error {
+    fn from(value: LookupError) -> Self {
+        Error::Lookup(value)
+    }
+}
+
+impl From<IgnoredError> for Error {
+    fn from(value: IgnoredError) -> Self {
+        Error::Ignored(value)
+    }
+}
+
+impl From<TimeoutError> for Error {
+    fn from(value: TimeoutError) -> Self {
+        Error::Timeout(value)
+    }
+}
+
+impl From<OverCurrentError> for Error {
+    fn from(value: OverCurrentError) -> Self {
+        Error::OverCurrent(value)
+    }
+}
+
+impl From<UnsupportedError> for Error {
+    fn from(value: UnsupportedError) -> Self {
+        Error::Unsupported(value)
+    }
+}
+
+#[derive(Debug)]
+pub struct IgnoredError {
+    pub port: u8,
+    pub reason: &'static str,
+}
+
+impl core::fmt::Display for IgnoredError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "hub ignored power request for port {}: {}",
+            self.port, self.reason
+        )
+    }
+}
+
+impl std::error::Error for IgnoredError {}
+
+#[derive(Debug)]
+pub struct TimeoutError {
+    pub port: u8,
+    pub timeout: Duration,
+    /// What didn't happen, such as "a device to appear"
+    pub waiting_for: &'static str,
+}
+
+impl core::fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "timed out after {:?} waiting for {} on port {}",
+            self.timeout, self.waiting_for, self.port
+        )
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+#[derive(Debug)]
+pub struct OverCurrentError {
+    pub port: u8,
+    /// How many times the port was powered again before giving up
+    pub attempts: u32,
+}
+
+impl core::fmt::Display for OverCurrentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "port {} is reporting over-current", self.port)?;
+        if self.attempts > 0 {
+            write!(f, " after {} attempts to power it again", self.attempts)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for OverCurrentError {}
+
+#[derive(Debug)]
+pub struct UnsupportedError {
+    /// What the hub doesn't have, such as "link states"
+    pub what: &'static str,
+}
+
+impl core::fmt::Display for UnsupportedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the hub doesn't support {}", self.what)
+    }
+}
+
+impl std::error::Error for UnsupportedError {}
@@ -0,0 +1,676 @@
+//! Events on a hub's ports.
+//!
+//! A hub reports which of its ports have changed on its status change
+//! endpoint, an interrupt IN endpoint that completes with a bitmap whenever
+//! a change bit is set: bit 0 for the hub itself and bit N for port N
+//! (USB 2.0 §11.12.4). hubctl reads that endpoint where the platform lets it
+//! claim the hub's interface, which usually means no kernel driver is bound
+//! to the hub. Everywhere else it falls back to polling the status of every
+//! port. Either way, [`HubEvents`] turns what changed into [`PortEvent`]s,
+//! and changes to the hub as a whole into [`HubEvent`]s.
+
+use std::future::Future;
+use std::time::Duration;
+
+use futures_lite::Stream;
+use nusb::transfer::{In, Interrupt, TransferError};
+use serde::Serialize;
+
+use crate::control::HubControl;
+use crate::device::UsbDevice;
+use crate::error::{Error, UnsupportedError};
+use crate::selector::Location;
+use crate::status::{HubStatus, PortStatus};
+use crate::topology::Hub;
+use crate::transport::{Transport, UsbTransport};
+
+/// The address of a hub's status change endpoint. The specifications only
+/// say that a hub has a single interrupt IN endpoint, but every hub seen so
+/// far uses endpoint 1.
+pub const STATUS_CHANGE_ENDPOINT: u8 = 0x81;
+
+/// Something that says when ports of a hub may have changed.
+pub trait ChangeSource {
+    /// Wait for the next status change bitmap.
+    fn next_change(&mut self) -> impl Future<Output = Result<Vec<u8>, TransferError>> + Send;
+
+    /// Whether the change bits of reported ports must be cleared for the
+    /// hub to stop reporting them. This is only safe when hubctl owns the
+    /// hub, as otherwise the OS's hub driver relies on seeing them.
+    fn clears_changes(&self) -> bool {
+        false
+    }
+}
+
+/// The ports whose bits are set in a status change bitmap. The hub's own
+/// bit is left out, and so are bits past the last port a hub can have, as
+/// the bitmap is as long as whatever the endpoint sent.
+pub fn changed_ports(bitmap: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    (1..(bitmap.len() * 8).min(u8::MAX as usize + 1))
+        .filter(|bit| bitmap[bit / 8] & (1 << (bit % 8)) != 0)
+        .map(|bit| bit as u8)
+}
+
+/// Whether the hub's own bit is set in a status change bitmap.
+fn hub_changed(bitmap: &[u8]) -> bool {
+    bitmap.first().is_some_and(|byte| byte & 1 != 0)
+}
+
+/// A bitmap with a bit set for the hub and every port.
+fn all_ports(port_count: u8) -> Vec<u8> {
+    let mut bitmap = vec![0; (port_count as usize + 1).div_ceil(8)];
+    for port in 0..=port_count as usize {
+        bitmap[port / 8] |= 1 << (port % 8);
+    }
+    bitmap
+}
+
+/// Reads the bitmap from the hub's status change endpoint.
+pub struct InterruptEndpoint {
+    endpoint: nusb::Endpoint<Interrupt, In>,
+}
+
+impl InterruptEndpoint {
+    /// Claim the hub's interface through `control`, which already has it
+    /// open, and open its status change endpoint. This fails if another
+    /// driver has the hub, which is the normal case on Linux, and hubctl
+    /// doesn't detach it as that would cut off every device below the hub.
+    /// Hubs in a replay don't have one.
+    pub async fn open(control: &HubControl) -> Result<Self, Error> {
+        let Some(handle) = control.handle() else {
+            return Err(UnsupportedError {
+                what: "a status change endpoint in a replay",
+            }
+            .into());
+        };
+        // Windows platforms only open hubs through their interface.
+        #[cfg(windows)]
+        let interface = handle;
+        #[cfg(not(windows))]
+        let interface = &handle.claim_interface(0).await?;
+        let endpoint = interface.endpoint::<Interrupt, In>(STATUS_CHANGE_ENDPOINT)?;
+        Ok(InterruptEndpoint { endpoint })
+    }
+}
+
+impl ChangeSource for InterruptEndpoint {
+    async fn next_change(&mut self) -> Result<Vec<u8>, TransferError> {
+        if self.endpoint.pending() == 0 {
+            let length = self.endpoint.max_packet_size();
+            let buffer = self.endpoint.allocate(length);
+            self.endpoint.submit(buffer);
+        }
+        let completion = self.endpoint.next_complete().await;
+        completion.status?;
+        let bitmap =
+            completion.buffer[..completion.actual_len.min(completion.buffer.len())].to_vec();
+        self.endpoint.submit(completion.buffer);
+        log::trace!("Status change bitmap: {bitmap:02x?}");
+        Ok(bitmap)
+    }
+
+    fn clears_changes(&self) -> bool {
+        true
+    }
+}
+
+/// Reports every port as changed at a fixed interval.
+pub struct Polling {
+    interval: tokio::time::Interval,
+    bitmap: Vec<u8>,
+}
+
+impl Polling {
+    pub fn new(period: Duration, port_count: u8) -> Self {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        Polling {
+            interval,
+            bitmap: all_ports(port_count),
+        }
+    }
+}
+
+impl ChangeSource for Polling {
+    async fn next_change(&mut self) -> Result<Vec<u8>, TransferError> {
+        self.interval.tick().await;
+        Ok(self.bitmap.clone())
+    }
+}
+
+/// Whichever of the status change endpoint and polling could be used.
+pub enum AnySource {
+    Interrupt(InterruptEndpoint),
+    Polling(Polling),
+}
+
+impl ChangeSource for AnySource {
+    async fn next_change(&mut self) -> Result<Vec<u8>, TransferError> {
+        match self {
+            AnySource::Interrupt(source) => source.next_change().await,
+            AnySource::Polling(source) => source.next_change().await,
+        }
+    }
+
+    fn clears_changes(&self) -> bool {
+        match self {
+            AnySource::Interrupt(source) => source.clears_changes(),
+            AnySource::Polling(source) => source.clears_changes(),
+        }
+    }
+}
+
+/// Something that happened to a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum PortEvent {
+    PowerOn,
+    PowerOff,
+    Connected,
+    Disconnected,
+    Enabled,
+    Disabled,
+    Suspended,
+    Resumed,
+    OverCurrent,
+    OverCurrentCleared,
+    /// A port reset finished.
+    ResetComplete,
+}
+
+impl core::fmt::Display for PortEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortEvent::PowerOn => write!(f, "power ON"),
+            PortEvent::PowerOff => write!(f, "power off"),
+            PortEvent::Connected => write!(f, "connected"),
+            PortEvent::Disconnected => write!(f, "disconnected"),
+            PortEvent::Enabled => write!(f, "enabled"),
+            PortEvent::Disabled => write!(f, "disabled"),
+            PortEvent::Suspended => write!(f, "suspended"),
+            PortEvent::Resumed => write!(f, "resumed"),
+            PortEvent::OverCurrent => write!(f, "OVER-CURRENT"),
+            PortEvent::OverCurrentCleared => write!(f, "over-current cleared"),
+            PortEvent::ResetComplete => write!(f, "reset complete"),
+        }
+    }
+}
+
+/// An event on a port, along with the status that showed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortChange {
+    pub port: u8,
+    pub event: PortEvent,
+    pub status: PortStatus,
+}
+
+/// Something that happened to the hub as a whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum HubEvent {
+    LocalPowerLost,
+    LocalPowerRestored,
+    OverCurrent,
+    OverCurrentCleared,
+}
+
+impl core::fmt::Display for HubEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HubEvent::LocalPowerLost => write!(f, "local power LOST"),
+            HubEvent::LocalPowerRestored => write!(f, "local power restored"),
+            HubEvent::OverCurrent => write!(f, "OVER-CURRENT"),
+            HubEvent::OverCurrentCleared => write!(f, "over-current cleared"),
+        }
+    }
+}
+
+/// An event on the hub itself, along with the status that showed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HubStatusChange {
+    pub event: HubEvent,
+    pub status: HubStatus,
+}
+
+/// Whichever of a port and the hub itself something happened to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnyChange {
+    Port(PortChange),
+    Hub(HubStatusChange),
+}
+
+/// Work out what happened to a port between two readings of its status.
+/// Change bits that weren't set in `before` catch a state that flipped and
+/// flipped back in between, such as a device being unplugged and plugged
+/// back in.
+pub fn port_events(before: &PortStatus, after: &PortStatus) -> Vec<PortEvent> {
+    let mut events = vec![];
+    let mut check = |was: bool, is: bool, changed: bool, on: PortEvent, off: PortEvent| {
+        if was != is {
+            events.push(if is { on } else { off });
+        } else if changed {
+            events.extend(if is { [off, on] } else { [on, off] });
+        }
+    };
+    check(
+        before.powered(),
+        after.powered(),
+        false,
+        PortEvent::PowerOn,
+        PortEvent::PowerOff,
+    );
+    check(
+        before.connected(),
+        after.connected(),
+        after.connection_changed() && !before.connection_changed(),
+        PortEvent::Connected,
+        PortEvent::Disconnected,
+    );
+    check(
+        before.enabled(),
+        after.enabled(),
+        false,
+        PortEvent::Enabled,
+        PortEvent::Disabled,
+    );
+    check(
+        before.suspended(),
+        after.suspended(),
+        false,
+        PortEvent::Suspended,
+        PortEvent::Resumed,
+    );
+    check(
+        before.over_current(),
+        after.over_current(),
+        after.over_current_changed() && !before.over_current_changed(),
+        PortEvent::OverCurrent,
+        PortEvent::OverCurrentCleared,
+    );
+    if (after.reset_changed() && !before.reset_changed())
+        || (after.bh_reset_changed() && !before.bh_reset_changed())
+    {
+        events.push(PortEvent::ResetComplete);
+    }
+    events
+}
+
+/// Work out what happened to the hub between two readings of its status,
+/// the same way as [`port_events`].
+pub fn hub_events(before: &HubStatus, after: &HubStatus) -> Vec<HubEvent> {
+    let mut events = vec![];
+    let mut check = |was: bool, is: bool, changed: bool, on: HubEvent, off: HubEvent| {
+        if was != is {
+            events.push(if is { on } else { off });
+        } else if changed {
+            events.extend(if is { [off, on] } else { [on, off] });
+        }
+    };
+    check(
+        before.local_power_lost(),
+        after.local_power_lost(),
+        after.local_power_changed() && !before.local_power_changed(),
+        HubEvent::LocalPowerLost,
+        HubEvent::LocalPowerRestored,
+    );
+    check(
+        before.over_current(),
+        after.over_current(),
+        after.over_current_changed() && !before.over_current_changed(),
+        HubEvent::OverCurrent,
+        HubEvent::OverCurrentCleared,
+    );
+    events
+}
+
+/// The events on one hub and every one of its ports.
+pub struct HubEvents<C = AnySource, T = UsbTransport> {
+    location: String,
+    control: HubControl<T>,
+    source: C,
+    hub_status: Option<HubStatus>,
+    statuses: Vec<Option<PortStatus>>,
+}
+
+impl HubEvents {
+    /// Watch a hub through its status change endpoint if it can be claimed,
+    /// or by reading port status every `interval` otherwise.
+    pub async fn open(hub: &Hub, interval: Duration) -> Result<Self, Error> {
+        let control = HubControl::new(hub.info()).await?;
+        let source = match InterruptEndpoint::open(&control).await {
+            Ok(endpoint) => {
+                log::debug!("Reading status changes of {} from its endpoint", hub.name());
+                AnySource::Interrupt(endpoint)
+            }
+            Err(e) => {
+                log::debug!("Polling {}, as its endpoint can't be used: {e}", hub.name());
+                AnySource::Polling(Polling::new(interval, hub.port_count()))
+            }
+        };
+        Ok(HubEvents::new(hub, control, source).await)
+    }
+}
+
+impl<C: ChangeSource, T: Transport> HubEvents<C, T> {
+    /// Start watching a hub, taking its current port status as the starting
+    /// point.
+    pub async fn new<D: UsbDevice>(hub: &Hub<D>, control: HubControl<T>, source: C) -> Self {
+        let mut events = HubEvents {
+            location: Location::of(hub.info()).to_string(),
+            control,
+            source,
+            hub_status: None,
+            statuses: vec![None; hub.port_count() as usize],
+        };
+        events.hub_status = events.read_hub().await.ok().map(|(_, kept)| kept);
+        for port in 1..=hub.port_count() {
+            events.statuses[port as usize - 1] = events.read(port).await.ok().map(|(_, kept)| kept);
+        }
+        events
+    }
+
+    /// The location of the hub, written as `--location` takes it.
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+
+    /// The hub, for sending it requests of its own.
+    pub fn control(&self) -> &HubControl<T> {
+        &self.control
+    }
+
+    /// Read a port's status, acknowledging its changes if that's up to us.
+    /// Returns the status as read, and as it should be remembered.
+    async fn read(&self, port: u8) -> Result<(PortStatus, PortStatus), Error> {
+        let status = self.control.status(port).await?;
+        if self.source.clears_changes() {
+            self.control.acknowledge(port, &status.changed()).await?;
+            return Ok((status, status.acknowledged()));
+        }
+        Ok((status, status))
+    }
+
+    /// Read the hub's own status, acknowledging its changes if that's up to
+    /// us, as [`HubEvents::read`] does for ports.
+    async fn read_hub(&self) -> Result<(HubStatus, HubStatus), Error> {
+        let status = self.control.hub_status().await?;
+        if self.source.clears_changes() {
+            self.control.acknowledge_hub(&status.changed()).await?;
+            return Ok((status, status.acknowledged()));
+        }
+        Ok((status, status))
+    }
+
+    /// Wait until something happens on the hub or one of its ports. Ports
+    /// whose status can't be read are skipped, and compared against their
+    /// last known status next time, and the same goes for the hub.
+    pub async fn next(&mut self) -> Result<Vec<AnyChange>, Error> {
+        loop {
+            let bitmap = self.source.next_change().await?;
+            let mut changes = vec![];
+            if hub_changed(&bitmap) {
+                match self.read_hub().await {
+                    Ok((read, kept)) => {
+                        if read.over_current()
+                            && self.hub_status.is_none_or(|last| !last.over_current())
+                        {
+                            log::warn!(
+                                "{} is reporting over-current on all of its ports",
+                                self.location
+                            );
+                        }
+                        if read.local_power_lost()
+                            && self.hub_status.is_none_or(|last| !last.local_power_lost())
+                        {
+                            log::warn!("{} has lost its external power supply", self.location);
+                        }
+                        if let Some(last) = self.hub_status {
+                            changes.extend(hub_events(&last, &read).into_iter().map(|event| {
+                                AnyChange::Hub(HubStatusChange {
+                                    event,
+                                    status: read,
+                                })
+                            }));
+                        }
+                        self.hub_status = Some(kept);
+                    }
+                    Err(e) => log::debug!("Couldn't read status of {}: {e}", self.location),
+                }
+            }
+            for port in changed_ports(&bitmap) {
+                let Some(last) = self.statuses.get(port as usize - 1).copied() else {
+                    log::debug!("{} reported a change on missing port {port}", self.location);
+                    continue;
+                };
+                let (read, kept) = match self.read(port).await {
+                    Ok(statuses) => statuses,
+                    Err(e) => {
+                        log::debug!("Couldn't read status of {} port {port}: {e}", self.location);
+                        continue;
+                    }
+                };
+                if read.over_current() && last.is_none_or(|last| !last.over_current()) {
+                    log::warn!("{} port {port} is reporting over-current", self.location);
+                }
+                if let Some(last) = last {
+                    changes.extend(port_events(&last, &read).into_iter().map(|event| {
+                        AnyChange::Port(PortChange {
+                            port,
+                            event,
+                            status: read,
+                        })
+                    }));
+                }
+                self.statuses[port as usize - 1] = Some(kept);
+            }
+            if !changes.is_empty() {
+                return Ok(changes);
+            }
+        }
+    }
+
+    /// Turn the events into a stream, which ends after the first error,
+    /// such as the hub being unplugged.
+    pub fn into_stream(self) -> impl Stream<Item = Result<AnyChange, Error>> {
+        futures_lite::stream::unfold(
+            (Some(self), Vec::new().into_iter()),
+            |(mut events, mut pending)| async move {
+                loop {
+                    if let Some(change) = pending.next() {
+                        return Some((Ok(change), (events, pending)));
+                    }
+                    match events.as_mut()?.next().await {
+                        Ok(changes) => pending = changes.into_iter(),
+                        Err(e) => return Some((Err(e), (None, pending))),
+                    }
+                }
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimDevice, SimHub};
+
+    async fn events<C: ChangeSource>(sim: &SimHub, source: C) -> HubEvents<C, SimHub> {
+        let control = HubControl::with_transport(sim.clone(), false);
+        let hub = sim.describe(&sim.devices()).await;
+        HubEvents::new(&hub, control, source).await
+    }
+
+    fn summary(changes: &[AnyChange]) -> Vec<(u8, PortEvent)> {
+        changes
+            .iter()
+            .filter_map(|change| match change {
+                AnyChange::Port(change) => Some((change.port, change.event)),
+                AnyChange::Hub(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bitmaps_name_ports() {
+        assert_eq!(changed_ports(&[0b0001_0101]).collect::<Vec<_>>(), [2, 4]);
+        assert_eq!(changed_ports(&[0x00, 0x02]).collect::<Vec<_>>(), [9]);
+        // A 64-byte endpoint can set bits no port number reaches.
+        let mut long = [0; 64];
+        long[31] = 0x80;
+        long[32] = 0x01;
+        long[63] = 0x80;
+        assert_eq!(changed_ports(&long).collect::<Vec<_>>(), [255]);
+        assert_eq!(all_ports(7), [0xff]);
+        assert_eq!(all_ports(8), [0xff, 0x01]);
+        assert!(hub_changed(&all_ports(4)));
+        assert!(!hub_changed(&[0b0001_0100]));
+    }
+
+    #[tokio::test]
+    async fn status_changes_become_events() {
+        let sim = SimHub::test_hub(4);
+        let mut events = events(&sim, sim.clone()).await;
+        assert_eq!(events.location(), "1-2");
+
+        sim.attach(2, SimDevice::new(0x1366, 0x0105));
+        let changes = events.next().await.unwrap();
+        assert_eq!(
+            summary(&changes),
+            [(2, PortEvent::Connected), (2, PortEvent::Enabled)]
+        );
+        assert!(matches!(changes[0], AnyChange::Port(change) if change.status.connected()));
+
+        sim.set_over_current(3, true);
+        assert_eq!(
+            summary(&events.next().await.unwrap()),
+            [(3, PortEvent::PowerOff), (3, PortEvent::OverCurrent)]
+        );
+    }
+
+    #[tokio::test]
+    async fn changes_are_acknowledged_when_owning_the_hub() {
+        let sim = SimHub::test_hub(4);
+        let mut events = events(&sim, sim.clone()).await;
+        sim.attach(1, SimDevice::new(0x1366, 0x0105));
+        events.next().await.unwrap();
+        let control = HubControl::with_transport(sim.clone(), false);
+        assert_eq!(
+            control.status(1).await.unwrap().changes(),
+            Vec::<&str>::new()
+        );
+
+        // A quick unplug and replug only shows up in the change bit.
+        sim.detach(1);
+        sim.attach(1, SimDevice::new(0x1366, 0x0105));
+        assert_eq!(
+            summary(&events.next().await.unwrap()),
+            [(1, PortEvent::Disconnected), (1, PortEvent::Connected)]
+        );
+    }
+
+    #[tokio::test]
+    async fn polling_leaves_change_bits_alone() {
+        let sim = SimHub::test_hub(4);
+        let polling = Polling::new(Duration::from_millis(100), 4);
+        let mut events = events(&sim, polling).await;
+        HubControl::with_transport(sim.clone(), false)
+            .off(4)
+            .await
+            .unwrap();
+        sim.attach(1, SimDevice::new(0x1366, 0x0105));
+        assert_eq!(
+            summary(&events.next().await.unwrap()),
+            [
+                (1, PortEvent::Connected),
+                (1, PortEvent::Enabled),
+                (4, PortEvent::PowerOff)
+            ]
+        );
+
+        let control = HubControl::with_transport(sim.clone(), false);
+        assert_eq!(
+            control.status(1).await.unwrap().changes(),
+            ["C_PORT_CONNECTION"]
+        );
+    }
+
+    #[tokio::test]
+    async fn hub_over_current_is_an_event() {
+        let sim = SimHub::test_hub(4);
+        let mut events = events(&sim, sim.clone()).await;
+
+        // Nothing else clears the hub's change bit, so this would never
+        // return if it weren't handled.
+        sim.set_hub_over_current(true);
+        let changes = events.next().await.unwrap();
+        assert!(matches!(
+            changes.as_slice(),
+            [AnyChange::Hub(HubStatusChange {
+                event: HubEvent::OverCurrent,
+                ..
+            })]
+        ));
+        let control = HubControl::with_transport(sim.clone(), false);
+        assert!(control.hub_status().await.unwrap().changed().is_empty());
+
+        sim.set_hub_over_current(false);
+        sim.set_local_power_lost(true);
+        let events: Vec<HubEvent> = events
+            .next()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|change| match change {
+                AnyChange::Hub(change) => Some(change.event),
+                AnyChange::Port(_) => None,
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [HubEvent::LocalPowerLost, HubEvent::OverCurrentCleared]
+        );
+    }
+
+    /// A hub that has gone away.
+    struct Unplugged;
+
+    impl ChangeSource for Unplugged {
+        async fn next_change(&mut self) -> Result<Vec<u8>, TransferError> {
+            Err(TransferError::Disconnected)
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_ends_after_an_error() {
+        let sim = SimHub::test_hub(4);
+        sim.attach(1, SimDevice::new(0x1366, 0x0105));
+        let stream = events(&sim, sim.clone()).await.into_stream();
+        sim.attach(2, SimDevice::new(0x0403, 0x6001));
+        let changes: Vec<_> =
+            futures_lite::StreamExt::collect(futures_lite::StreamExt::take(stream, 2)).await;
+        assert!(matches!(
+            changes.as_slice(),
+            [
+                Ok(AnyChange::Port(PortChange {
+                    port: 2,
+                    event: PortEvent::Connected,
+                    ..
+                })),
+                Ok(AnyChange::Port(PortChange {
+                    port: 2,
+                    event: PortEvent::Enabled,
+                    ..
+                }))
+            ]
+        ));
+
+        let stream = events(&sim, Unplugged).await.into_stream();
+        let changes: Vec<_> = futures_lite::StreamExt::collect(stream).await;
+        assert!(matches!(
+            changes.as_slice(),
+            [Err(Error::Transfer(TransferError::Disconnected))]
+        ));
+    }
+}
@@ -0,0 +1,267 @@
+//! Drawing the [`Tree`] as a graph, for Graphviz or Mermaid to render.
+//!
+//! Each bus, hub port and device becomes a node. Ports are drawn whether or
+//! not anything is plugged into them, and show whether they're powered.
+
+use std::fmt::Write;
+
+use crate::device::UsbDevice;
+use crate::selector::Location;
+use crate::topology;
+use crate::tree::{Node, Port, Tree};
+
+/// Write `tree` in Graphviz DOT.
+pub fn dot<D: UsbDevice>(tree: &Tree<D>) -> String {
+    let mut out = String::new();
+    out.push_str("digraph usb {\n");
+    out.push_str("    rankdir=LR;\n");
+    out.push_str("    node [shape=box, fontname=\"sans-serif\"];\n");
+    for element in elements(tree) {
+        let _ = match element {
+            Element::Bus { id, label } => writeln!(
+                out,
+                "    {id} [label=\"{}\", shape=oval];",
+                dot_escape(&label)
+            ),
+            Element::Device { id, lines, hub } => writeln!(
+                out,
+                "    {id} [label=\"{}\"{}];",
+                lines
+                    .iter()
+                    .map(|line| dot_escape(line))
+                    .collect::<Vec<_>>()
+                    .join("\\n"),
+                if hub { ", style=bold" } else { "" }
+            ),
+            Element::Port { id, label, power } => writeln!(
+                out,
+                "    {id} [label=\"{}\", {}];",
+                dot_escape(&label),
+                match power {
+                    Power::On => "style=\"rounded,filled\", fillcolor=palegreen",
+                    Power::Off => "style=\"rounded,dashed\"",
+                    Power::Unknown => "style=rounded",
+                }
+            ),
+            Element::Edge { from, to } => writeln!(out, "    {from} -> {to};"),
+        };
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Write `tree` as a Mermaid flowchart.
+pub fn mermaid<D: UsbDevice>(tree: &Tree<D>) -> String {
+    let mut out = String::new();
+    out.push_str("flowchart LR\n");
+    let mut powered = vec![];
+    let mut unpowered = vec![];
+    for element in elements(tree) {
+        let _ = match element {
+            Element::Bus { id, label } => {
+                writeln!(out, "    {id}([\"{}\"])", mermaid_escape(&label))
+            }
+            Element::Device { id, lines, .. } => writeln!(
+                out,
+                "    {id}[\"{}\"]",
+                lines
+                    .iter()
+                    .map(|line| mermaid_escape(line))
+                    .collect::<Vec<_>>()
+                    .join("<br>")
+            ),
+            Element::Port { id, label, power } => {
+                match power {
+                    Power::On => powered.push(id.clone()),
+                    Power::Off => unpowered.push(id.clone()),
+                    Power::Unknown => {}
+                }
+                writeln!(out, "    {id}(\"{}\")", mermaid_escape(&label))
+            }
+            Element::Edge { from, to } => writeln!(out, "    {from} --> {to}"),
+        };
+    }
+    out.push_str("    classDef on fill:#cfc\n");
+    out.push_str("    classDef off stroke-dasharray:5 5,color:#888\n");
+    if !powered.is_empty() {
+        let _ = writeln!(out, "    class {} on", powered.join(","));
+    }
+    if !unpowered.is_empty() {
+        let _ = writeln!(out, "    class {} off", unpowered.join(","));
+    }
+    out
+}
+
+#[derive(Clone, Copy)]
+enum Power {
+    On,
+    Off,
+    Unknown,
+}
+
+/// The parts of a graph, independent of how it's written out.
+enum Element {
+    Bus {
+        id: String,
+        label: String,
+    },
+    Device {
+        id: String,
+        lines: Vec<String>,
+        hub: bool,
+    },
+    Port {
+        id: String,
+        label: String,
+        power: Power,
+    },
+    Edge {
+        from: String,
+        to: String,
+    },
+}
+
+fn elements<D: UsbDevice>(tree: &Tree<D>) -> Vec<Element> {
+    let mut elements = vec![];
+    for bus in tree.buses() {
+        let id = format!("bus_{}", node_id(bus.id()));
+        elements.push(Element::Bus {
+            id: id.clone(),
+            label: match bus.id().parse::<u32>() {
+                Ok(number) => format!("Bus {number}"),
+                Err(_) => format!("Bus {}", bus.id()),
+            },
+        });
+        for node in bus.nodes() {
+            add_node(&mut elements, &id, node);
+        }
+    }
+    elements
+}
+
+fn add_node<D: UsbDevice>(elements: &mut Vec<Element>, parent: &str, node: &Node<D>) {
+    let info = node.info();
+    let location = Location::of(info);
+    let id = format!("dev_{}", node_id(&location.to_string()));
+    let mut lines = vec![];
+    match node {
+        Node::Hub(_) => lines.push(format!(
+            "Hub {}",
+            usb_ids::Device::from_vid_pid(info.vendor_id(), info.product_id())
+                .map(|device| device.name())
+                .or(info.product_string())
+                .unwrap_or("[unknown product]")
+        )),
+        Node::Device(_) => lines.push(topology::device_name(Some(info))),
+    }
+    lines.push(format!(
+        "{:04x}:{:04x} @ {location}",
+        info.vendor_id(),
+        info.product_id()
+    ));
+    elements.push(Element::Device {
+        id: id.clone(),
+        lines,
+        hub: matches!(node, Node::Hub(_)),
+    });
+    elements.push(Element::Edge {
+        from: parent.to_owned(),
+        to: id.clone(),
+    });
+
+    if let Node::Hub(hub) = node {
+        for port in hub.ports() {
+            add_port(elements, &id, port);
+        }
+    }
+}
+
+fn add_port<D: UsbDevice>(elements: &mut Vec<Element>, hub: &str, port: &Port<D>) {
+    let id = format!("{hub}_port_{}", port.number());
+    let power = match port.status() {
+        Some(status) if status.powered() => Power::On,
+        Some(_) => Power::Off,
+        None => Power::Unknown,
+    };
+    let state = match power {
+        Power::On => "ON",
+        Power::Off => "off",
+        Power::Unknown => "unknown",
+    };
+    let label = match port.attached() {
+        Some(_) => format!("Port {}: {state}", port.number()),
+        None => format!("Port {}: {state}, empty", port.number()),
+    };
+    elements.push(Element::Port {
+        id: id.clone(),
+        label,
+        power,
+    });
+    elements.push(Element::Edge {
+        from: hub.to_owned(),
+        to: id.clone(),
+    });
+    if let Some(attached) = port.attached() {
+        add_node(elements, &id, attached);
+    }
+}
+
+/// Make an identifier that both DOT and Mermaid accept without quoting.
+fn node_id(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HubControl;
+    use crate::sim::{SimDevice, SimHub};
+
+    async fn tree() -> Tree<SimDevice> {
+        let sim = SimHub::test_hub(2);
+        sim.attach(
+            1,
+            SimDevice::new(0x1234, 0x5678).with_product("Probe \"A\""),
+        );
+        let control = HubControl::with_transport(sim.clone(), false);
+        control.off(2).await.unwrap();
+        let devices = sim.devices();
+        Tree::new([sim.describe_with_status(&devices).await], &devices)
+    }
+
+    #[tokio::test]
+    async fn dot_has_every_port() {
+        let dot = dot(&tree().await);
+        assert!(dot.starts_with("digraph usb {\n"));
+        assert!(dot.contains("    bus_1 [label=\"Bus 1\", shape=oval];\n"));
+        assert!(dot.contains("    bus_1 -> dev_1_2;\n"));
+        assert!(dot.contains("    dev_1_2 -> dev_1_2_port_1;\n"));
+        assert!(dot.contains("    dev_1_2_port_1 -> dev_1_2_1;\n"));
+        assert!(dot.contains(
+            "    dev_1_2_port_2 [label=\"Port 2: off, empty\", style=\"rounded,dashed\"];\n"
+        ));
+        assert!(dot.contains("Probe \\\"A\\\" from [unknown vendor]\\n1234:5678 @ 1-2.1"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[tokio::test]
+    async fn mermaid_marks_power_state() {
+        let mermaid = mermaid(&tree().await);
+        assert!(mermaid.starts_with("flowchart LR\n"));
+        assert!(mermaid.contains("    dev_1_2_port_1(\"Port 1: ON\")\n"));
+        assert!(mermaid.contains("    dev_1_2_port_1 --> dev_1_2_1\n"));
+        assert!(mermaid.contains("Probe #quot;A#quot; from [unknown vendor]<br>1234:5678 @ 1-2.1"));
+        assert!(mermaid.contains("    class dev_1_2_port_1 on\n"));
+        assert!(mermaid.contains("    class dev_1_2_port_2 off\n"));
+    }
+}
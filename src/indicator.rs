@@ -0,0 +1,114 @@
+//! Driving port indicator LEDs, to find a port on the hub itself.
+//!
+//! USB 2.0 hubs that set the port indicator bit in `wHubCharacteristics`
+//! let the host pick the colour of each port's LED with
+//! SetFeature(PORT_INDICATOR) (USB 2.0 §11.5.3). Otherwise the hub lights
+//! them automatically to show the port's state. SuperSpeed hubs don't have
+//! port indicators.
+
+use std::time::Duration;
+
+use crate::control::HubControl;
+use crate::error::Error;
+use crate::transport::Transport;
+
+/// How long each colour is shown while blinking.
+pub const BLINK_PERIOD: Duration = Duration::from_millis(250);
+
+/// The colour of a port indicator, or automatic mode where the hub chooses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indicator {
+    Automatic,
+    Amber,
+    Green,
+    Off,
+}
+
+impl Indicator {
+    /// The indicator selector, sent in the upper byte of `wIndex`.
+    pub(crate) fn selector(self) -> u8 {
+        match self {
+            Indicator::Automatic => 0,
+            Indicator::Amber => 1,
+            Indicator::Green => 2,
+            Indicator::Off => 3,
+        }
+    }
+
+    pub(crate) fn from_selector(selector: u8) -> Option<Self> {
+        Some(match selector {
+            0 => Indicator::Automatic,
+            1 => Indicator::Amber,
+            2 => Indicator::Green,
+            3 => Indicator::Off,
+            _ => return None,
+        })
+    }
+}
+
+/// Blink the indicator of `port` between amber and green for `duration`,
+/// then hand it back to the hub. The indicator is put back in automatic
+/// mode even if one of the requests in between fails.
+pub async fn identify<T: Transport>(
+    control: &HubControl<T>,
+    port: u8,
+    duration: Duration,
+) -> Result<(), Error> {
+    let blinking = blink(control, port, duration).await;
+    let restored = control.set_indicator(port, Indicator::Automatic).await;
+    blinking.and(restored)
+}
+
+async fn blink<T: Transport>(
+    control: &HubControl<T>,
+    port: u8,
+    duration: Duration,
+) -> Result<(), Error> {
+    let deadline = tokio::time::Instant::now() + duration;
+    let colours = [
+        Indicator::Amber,
+        Indicator::Off,
+        Indicator::Green,
+        Indicator::Off,
+    ];
+    for colour in colours.into_iter().cycle() {
+        if tokio::time::Instant::now() >= deadline {
+            break;
+        }
+        control.set_indicator(port, colour).await?;
+        tokio::time::sleep(BLINK_PERIOD).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimHub;
+
+    #[tokio::test]
+    async fn identify_restores_automatic_mode() {
+        let sim = SimHub::test_hub(4).with_port_indicators();
+        let control = HubControl::with_transport(sim.clone(), false);
+
+        control.set_indicator(3, Indicator::Green).await.unwrap();
+        assert!(control.status(3).await.unwrap().indicator_control());
+        assert_eq!(sim.indicator(3), Indicator::Green);
+
+        identify(&control, 3, Duration::from_millis(600))
+            .await
+            .unwrap();
+        assert!(!control.status(3).await.unwrap().indicator_control());
+        assert_eq!(sim.indicator(3), Indicator::Automatic);
+    }
+
+    #[tokio::test]
+    async fn hub_without_indicators_stalls() {
+        let sim = SimHub::test_hub(4);
+        let control = HubControl::with_transport(sim.clone(), false);
+        assert!(matches!(
+            identify(&control, 1, Duration::from_secs(1)).await,
+            Err(Error::Transfer(nusb::transfer::TransferError::Stall))
+        ));
+    }
+}
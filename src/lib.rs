@@ -0,0 +1,42 @@
+//! Control power to the ports of USB hubs.
+//!
+//! Hubs are found with [`topology::discover`] or [`topology::find`], and
+//! opened with [`HubControl`] to read port status and switch power:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), hubctl::Error> {
+//! let devices = hubctl::device::list().await?;
+//! for hub in hubctl::topology::discover(&devices).await {
+//!     let control = hubctl::HubControl::new(hub.info()).await?;
+//!     for port in 1..=hub.port_count() {
+//!         println!("{} port {port}: {}", hub.name(), control.status(port).await?);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod bos;
+pub mod companion;
+mod control;
+pub mod descriptor;
+pub mod device;
+pub mod error;
+pub mod events;
+pub mod graph;
+pub mod indicator;
+pub mod power;
+pub mod record;
+pub mod recovery;
+pub mod report;
+pub mod selector;
+pub mod sim;
+pub mod status;
+pub mod topology;
+pub mod transport;
+pub mod tree;
+pub mod watch;
+
+pub use control::HubControl;
+pub use error::Error;
+pub use topology::Hub;
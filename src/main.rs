@@ -1,171 +1,113 @@
-use std::time::Duration;
-use usb_ids::FromId;
+use std::{
+    collections::HashMap,
+    process::ExitCode,
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::Parser;
+use futures_lite::StreamExt;
+use serde::Serialize;
 
-use nusb::{
-    Device, DeviceInfo,
-    transfer::{ControlIn, ControlOut, ControlType, Recipient, TransferError},
+use hubctl::{
+    Error, Hub, HubControl, bos,
+    companion::{companion, find_companion},
+    device::{self, Device, UsbDevice},
+    events::{AnyChange, HubEvents, PortEvent},
+    graph,
+    indicator::{self, Indicator},
+    power::{power_switching_caveat, verify_power, wait_for_device},
+    record::{self, Recording, ReplaySession},
+    recovery::{self, RecoveryPolicy},
+    report,
+    selector::Location,
+    status::{Change, HubChange, PortStatus},
+    topology,
+    tree::{HubNode, Tree},
+    watch::{Devices, Event},
 };
 
-enum UsbDescriptorType {
-    Hub = 0x29,
-    SuperSpeedHub = 0x2a,
-}
+mod cli;
 
-enum UsbDeviceClass {
-    Hub = 0x09,
-}
+/// How long `cycle` leaves a port unpowered before turning it back on, unless
+/// the hub needs longer for its power to settle.
+const CYCLE_OFF_TIME: Duration = Duration::from_secs(1);
 
-enum UsbRequest {
-    GetStatus = 0,
-    ClearFeature = 1,
-    SetFeature = 3,
-    GetDescriptor = 6,
-}
+/// Exit status for command line errors, which matches what clap uses.
+const EXIT_USAGE: u8 = 2;
 
-/// Windows platforms must go through the Interface. Other platforms
-/// may not even allow claiming the Interface.
-struct HubControl(
-    #[cfg(windows)] nusb::Interface,
-    #[cfg(not(windows))] Device,
-    bool, /* SuperSpeed */
-);
-
-impl HubControl {
-    pub async fn new(device_info: &DeviceInfo) -> Result<Self, nusb::Error> {
-        log::trace!(
-            "Opening device {:04x}:{:04x}...",
-            device_info.vendor_id(),
-            device_info.product_id()
-        );
-        let is_superspeed = device_info.usb_version() >= 0x0300;
-        let device = device_info.open().await?;
-
-        Ok(HubControl(
-            #[cfg(windows)]
-            device.claim_interface(0).await?,
-            #[cfg(not(windows))]
-            device,
-            is_superspeed,
-        ))
-    }
-
-    pub async fn port_count(&self) -> Result<u8, TransferError> {
-        let data = ControlIn {
-            control_type: ControlType::Class,
-            recipient: Recipient::Device,
-            request: UsbRequest::GetDescriptor as _,
-            value: (if self.1 {
-                UsbDescriptorType::SuperSpeedHub
-            } else {
-                UsbDescriptorType::Hub
-            } as u16)
-                .to_be(),
-            index: 0,
-            length: 12,
-        };
-        let response = self.0.control_in(data, Duration::from_secs(5)).await?;
-        log::trace!("Port count data: {response:02x?}");
-        Ok(response[2])
-    }
-
-    pub async fn status(&self, port: u8) -> Result<bool, TransferError> {
-        let data = ControlIn {
-            control_type: ControlType::Class,
-            recipient: Recipient::Other,
-            request: UsbRequest::GetStatus as _,
-            value: 0,
-            index: port.into(),
-            length: 4,
-        };
-        let response = self.0.control_in(data, Duration::from_secs(1)).await?;
-        log::trace!("Port status data: {response:02x?}");
-        Ok(response[1] & 1 != 0)
-    }
+/// Exit status when the requested hub or port doesn't exist.
+const EXIT_NOT_FOUND: u8 = 3;
 
-    async fn set_port(&self, port: u8, enabled: bool) -> Result<(), TransferError> {
-        let off = ControlOut {
-            control_type: ControlType::Class,
-            recipient: Recipient::Other,
-            request: if enabled {
-                UsbRequest::SetFeature
-            } else {
-                UsbRequest::ClearFeature
-            } as _,
-            value: 1 << 3, /* FEAT_POWER */
-            index: port as _,
-            data: &[],
-        };
-        log::trace!("Turning port {}...", if enabled { "on" } else { "off" });
-        self.0.control_out(off, Duration::from_secs(5)).await?;
-        Ok(())
-    }
+/// Exit status when the hub couldn't be opened or rejected a request.
+const EXIT_USB_ERROR: u8 = 4;
 
-    #[allow(dead_code)]
-    pub async fn off(&self, port: u8) -> Result<(), TransferError> {
-        self.set_port(port, false).await
-    }
+/// Exit status when a power change was refused because the hub can't
+/// switch the port on its own, or the hub doesn't support the request at all,
+/// such as `identify` on a hub without port indicators.
+const EXIT_REFUSED: u8 = 5;
 
-    #[allow(dead_code)]
-    pub async fn on(&self, port: u8) -> Result<(), TransferError> {
-        self.set_port(port, true).await
-    }
+/// Exit status when the hub accepted a power change but didn't act on it.
+const EXIT_IGNORED: u8 = 6;
 
-    pub async fn toggle(&self, port: u8) -> Result<(), TransferError> {
-        self.set_port(port, !self.status(port).await?).await
-    }
-}
+/// Exit status when `cycle --wait` gave up waiting for the device, or a port
+/// didn't finish a reset, suspend or resume.
+const EXIT_TIMEOUT: u8 = 7;
+
+/// Exit status when a port tripped over-current.
+const EXIT_OVER_CURRENT: u8 = 8;
 
 struct TogglablePort {
     name: String,
-    enabled: bool,
+    status: Option<PortStatus>,
     index: u8,
 }
 
 impl core::fmt::Display for TogglablePort {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "    {}: {} -- {}",
-            self.index,
-            self.name,
-            if self.enabled { "ON" } else { "off" }
-        )
+        write!(f, "    {}: {} -- ", self.index, self.name)?;
+        match &self.status {
+            Some(status) => write!(f, "{status}"),
+            None => write!(f, "[unknown]"),
+        }
     }
 }
 
 struct TogglableDevice {
-    name: String,
+    hub: Hub,
     control: HubControl,
-    children: Vec<(String, bool /* port state */)>,
+    statuses: Vec<Option<PortStatus>>,
 }
 
 impl TogglableDevice {
-    async fn new(device: SelectableDevice) -> Result<TogglableDevice, nusb::Error> {
-        let control = HubControl::new(&device.info).await?;
-        let mut children = vec![];
-        for (index, child_name) in device.children.into_iter().enumerate() {
-            let port_status = control.status(index as u8 + 1).await.ok().unwrap_or(false);
-            children.push((child_name, port_status));
+    async fn new(hub: Hub) -> Result<TogglableDevice, Error> {
+        let control = HubControl::new(hub.info()).await?;
+        let mut statuses = vec![];
+        for port in 1..=hub.port_count() {
+            statuses.push(control.status(port).await.ok());
         }
         Ok(TogglableDevice {
-            name: device.name,
+            hub,
             control,
-            children,
+            statuses,
         })
     }
 
-    async fn toggle(&mut self, port: u8) -> Result<(), TransferError> {
-        self.control.toggle(port).await?;
-        self.children[port as usize - 1].1 = !self.children[port as usize - 1].1;
-        Ok(())
+    async fn toggle(&mut self, port: u8, verify: bool) -> eyre::Result<bool> {
+        let enabled = self.control.toggle(port).await?;
+        self.statuses[port as usize - 1] = if verify {
+            Some(verify_power(&self.control, &self.hub, port, enabled).await?)
+        } else {
+            self.control.status(port).await.ok()
+        };
+        Ok(enabled)
     }
 
     fn selection(&self) -> Vec<TogglablePort> {
         let mut ret = vec![];
-        for (index, child) in self.children.iter().enumerate() {
+        for (index, child) in self.hub.children().iter().enumerate() {
             ret.push(TogglablePort {
-                name: child.0.clone(),
-                enabled: child.1,
+                name: topology::device_name(child.as_ref()),
+                status: self.statuses[index],
                 index: index as u8 + 1,
             })
         }
@@ -175,136 +117,809 @@ impl TogglableDevice {
 
 impl core::fmt::Display for TogglableDevice {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.name)
+        write!(f, "{}", self.hub.name())
     }
 }
 
-struct SelectableDevice {
-    name: String,
-    info: DeviceInfo,
-    children: Vec<String>,
-}
+#[derive(Debug)]
+struct RefusedError(String);
 
-impl core::fmt::Display for SelectableDevice {
+impl core::fmt::Display for RefusedError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "{}", self.name)?;
-        for (index, child) in self.children.iter().enumerate() {
-            writeln!(f, "    {}: {child}", index + 1)?;
+        write!(f, "{}; pass --force to do it anyway", self.0)
+    }
+}
+
+impl std::error::Error for RefusedError {}
+
+/// A port opened for a power operation, along with the other half of the
+/// hub if it's a USB 3 hub, as the port stays powered until both halves
+/// have switched it off.
+struct PowerTarget {
+    hub: Hub,
+    control: HubControl,
+    port: u8,
+    companion: Option<(Hub, HubControl)>,
+}
+
+impl PowerTarget {
+    fn hubs(&self) -> impl Iterator<Item = (&Hub, &HubControl)> {
+        std::iter::once((&self.hub, &self.control))
+            .chain(self.companion.iter().map(|(hub, control)| (hub, control)))
+    }
+
+    /// Switch power to the port on both halves of the hub, optionally
+    /// checking that they really did it.
+    async fn set_power(&self, enabled: bool, verify: bool) -> eyre::Result<()> {
+        for (_, control) in self.hubs() {
+            control.set_power(self.port, enabled).await?;
+        }
+        if verify {
+            for (hub, control) in self.hubs() {
+                verify_power(control, hub, self.port, enabled).await?;
+            }
         }
         Ok(())
     }
+
+    /// Mentions the other half of the hub, if the port was switched there
+    /// too.
+    fn also(&self) -> String {
+        match &self.companion {
+            Some((hub, _)) => format!(" on both halves, with hub {}", Location::of(hub.info())),
+            None => String::new(),
+        }
+    }
 }
 
-fn get_name(device_info: &DeviceInfo) -> String {
-    format!(
-        "Hub {:04x}:{:04x} {} / {} / {} ({} / {}) @ {} {:?}",
-        device_info.vendor_id(),
-        device_info.product_id(),
-        device_info.product_string().unwrap_or("[no product name]"),
-        device_info
-            .manufacturer_string()
-            .unwrap_or("[no manufacturer]"),
-        device_info.serial_number().unwrap_or("[no serial number]"),
-        usb_ids::Vendor::from_id(device_info.vendor_id())
-            .map(|v| v.name())
-            .unwrap_or("[unknown vendor]"),
-        usb_ids::Device::from_vid_pid(device_info.vendor_id(), device_info.product_id())
-            .map(|v| v.name())
-            .unwrap_or("[unknown product]"),
-        device_info.bus_id(),
-        device_info.port_chain()
-    )
+/// Open the hub for a power operation, making sure the port exists and that
+/// the hub is able to switch it without affecting anything else.
+async fn open_port(args: &cli::PortArgs) -> eyre::Result<PowerTarget> {
+    let (selector, port) = args.target.resolve_port()?;
+    let (hub, port) = topology::find(&selector, Some(&port)).await?;
+    let port = port.expect("port was requested");
+    if let Some(caveat) = hub
+        .descriptor()
+        .and_then(|descriptor| power_switching_caveat(descriptor, port))
+    {
+        if !args.force {
+            return Err(RefusedError(caveat).into());
+        }
+        eprintln!("Warning: {caveat}");
+    }
+    let control = HubControl::new(hub.info()).await?;
+    let companion = if args.no_companion {
+        None
+    } else {
+        open_companion(&hub, port, args.force).await?
+    };
+    Ok(PowerTarget {
+        hub,
+        control,
+        port,
+        companion,
+    })
 }
 
-#[tokio::main]
-async fn main() -> eyre::Result<()> {
-    env_logger::init();
-    let devices = nusb::list_devices().await?;
-    let mut choices = vec![];
-    let devices: Vec<DeviceInfo> = devices.collect();
-    for device_info in &devices {
-        let name = get_name(device_info);
-        if device_info.class() != UsbDeviceClass::Hub as _ {
+/// Open the other half of `hub`, if it's a USB 3 hub, with the same checks
+/// as [`open_port`].
+async fn open_companion(
+    hub: &Hub,
+    port: u8,
+    force: bool,
+) -> eyre::Result<Option<(Hub, HubControl)>> {
+    let Some(companion) = find_companion(hub).await? else {
+        return Ok(None);
+    };
+    let location = Location::of(companion.info());
+    if companion.check_port(port).is_err() {
+        log::debug!("Companion hub {location} has no port {port}");
+        return Ok(None);
+    }
+    if let Some(caveat) = companion
+        .descriptor()
+        .and_then(|descriptor| power_switching_caveat(descriptor, port))
+    {
+        let caveat = format!("companion {caveat} (pass --no-companion to leave it alone)");
+        if !force {
+            return Err(RefusedError(caveat).into());
+        }
+        eprintln!("Warning: {caveat}");
+    }
+    let control = HubControl::new(companion.info()).await?;
+    Ok(Some((companion, control)))
+}
+
+/// Open the hub for a request that leaves port power alone, so any hub will
+/// do regardless of how it switches power.
+async fn open_target(target: &cli::Target) -> eyre::Result<(HubControl, u8)> {
+    let (selector, port) = target.resolve_port()?;
+    let (hub, port) = topology::find(&selector, Some(&port)).await?;
+    let port = port.expect("port was requested");
+    Ok((HubControl::new(hub.info()).await?, port))
+}
+
+async fn print_status(hub: Hub, port: Option<u8>) -> eyre::Result<()> {
+    let hub = TogglableDevice::new(hub).await?;
+    println!("{hub}");
+    if let Some(descriptor) = hub.hub.descriptor() {
+        println!("  {descriptor}");
+    }
+    let hub_status = hub
+        .control
+        .hub_status()
+        .await
+        .inspect_err(|e| log::debug!("Couldn't read hub status: {e}"))
+        .ok();
+    if let Some(status) = hub_status {
+        println!("  Hub status: {status}");
+    }
+    for entry in hub.selection() {
+        if port.is_none_or(|port| port == entry.index) {
+            println!("{entry}");
+        }
+    }
+
+    if let Some(status) = hub_status {
+        if status.local_power_lost() {
+            eprintln!(
+                "Warning: the hub has lost its external power supply, so its ports may be unpowered or limited to bus power"
+            );
+        }
+        if status.over_current() {
+            eprintln!("Warning: the hub is reporting over-current on all of its ports");
+        }
+    }
+    for entry in hub.selection() {
+        let Some(status) = entry.status else {
+            continue;
+        };
+        if port.is_some_and(|port| port != entry.index) {
             continue;
         }
-        let port_count = if let Ok(val) = HubControl::new(device_info).await {
-            if let Ok(count) = val.port_count().await {
-                Some(count)
-            } else {
-                None
-            }
+        if status.over_current() {
+            eprintln!(
+                "Warning: port {} is reporting over-current, so the hub has cut its power",
+                entry.index
+            );
+        } else if status.over_current_changed() {
+            eprintln!(
+                "Warning: port {} tripped over-current since its change bit was last cleared",
+                entry.index
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Print the hub's identity, its hub descriptor and the capabilities in its
+/// BOS descriptor.
+async fn print_info(hub: &Hub) -> eyre::Result<()> {
+    let info = hub.info();
+    println!("{}", hub.name());
+    println!(
+        "  USB {:x}.{:02x}, {}",
+        info.usb_version() >> 8,
+        info.usb_version() & 0xff,
+        if info.is_superspeed() {
+            "SuperSpeed half"
         } else {
-            None
+            "USB 2 hub"
+        }
+    );
+    if let Some(descriptor) = hub.descriptor() {
+        println!("  {descriptor}");
+    }
+    if let Some(other) = find_companion(hub).await? {
+        println!(
+            "  Other half of this USB 3 hub: {} @ {}",
+            other.index(),
+            Location::of(other.info())
+        );
+    }
+
+    let control = HubControl::new(info).await?;
+    match control.bos().await? {
+        Some(data) => {
+            println!("  BOS descriptor:");
+            for capability in bos::parse(&data) {
+                println!("    {capability}");
+            }
+        }
+        None => println!("  No BOS descriptor, as the hub is older than USB 2.01"),
+    }
+    Ok(())
+}
+
+/// Acknowledge whichever of `changes` are set on the selected ports of
+/// `hub`, and on the hub itself if no port was selected, saying which ones
+/// were unless printing JSON.
+async fn acknowledge_changes(
+    hub: &Hub,
+    port: Option<u8>,
+    changes: &cli::Changes,
+    format: cli::Format,
+) -> eyre::Result<()> {
+    let control = HubControl::new(hub.info()).await?;
+    if port.is_none() {
+        let acknowledged: Vec<HubChange> = control
+            .hub_status()
+            .await?
+            .changed()
+            .into_iter()
+            .filter(|change| changes.hub.contains(change))
+            .collect();
+        if !acknowledged.is_empty() {
+            control.acknowledge_hub(&acknowledged).await?;
+            if matches!(format, cli::Format::Text) {
+                let names: Vec<String> = acknowledged.iter().map(HubChange::to_string).collect();
+                println!(
+                    "Acknowledged {} on hub {}",
+                    names.join(", "),
+                    Location::of(hub.info())
+                );
+            }
+        }
+    }
+    let ports = match port {
+        Some(port) => port..=port,
+        None => 1..=hub.port_count(),
+    };
+    for port in ports {
+        let acknowledged: Vec<Change> = control
+            .status(port)
+            .await?
+            .changed()
+            .into_iter()
+            .filter(|change| changes.ports.contains(change))
+            .collect();
+        if acknowledged.is_empty() {
+            continue;
+        }
+        control.acknowledge(port, &acknowledged).await?;
+        if matches!(format, cli::Format::Text) {
+            let names: Vec<String> = acknowledged.iter().map(Change::to_string).collect();
+            println!(
+                "Acknowledged {} on {} port {port}",
+                names.join(", "),
+                Location::of(hub.info())
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_json(hubs: &[report::Hub]) -> eyre::Result<()> {
+    println!("{}", serde_json::to_string_pretty(hubs)?);
+    Ok(())
+}
+
+/// An event as `watch --format json` prints it.
+#[derive(Serialize)]
+struct TimedEvent<'a> {
+    /// Seconds since the Unix epoch
+    time: f64,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+fn print_event(event: &Event, start: Instant, format: cli::Format) -> eyre::Result<()> {
+    match format {
+        cli::Format::Json => {
+            let time = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            println!("{}", serde_json::to_string(&TimedEvent { time, event })?);
+        }
+        _ => println!("[{:10.3}s] {event}", start.elapsed().as_secs_f64()),
+    }
+    Ok(())
+}
+
+/// Print hotplug events and port changes as they happen. Hubs are watched
+/// as they come and go.
+async fn watch_ports(args: cli::WatchArgs, format: cli::Format) -> eyre::Result<()> {
+    if matches!(format, cli::Format::Dot | cli::Format::Mermaid) {
+        return Err(format.unsupported("watch").into());
+    }
+    let mut hotplug = nusb::watch_devices()?;
+    let devices = device::list().await?;
+    let mut known = Devices::new(&devices);
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut watched = HashMap::new();
+    watch_hubs(&mut watched, &devices, &args, &sender).await;
+    let start = Instant::now();
+
+    loop {
+        tokio::select! {
+            event = hotplug.next() => {
+                let Some(event) = event else {
+                    log::warn!("Hotplug event stream ended");
+                    return Ok(());
+                };
+                let (event, device) = known.update(event);
+                print_event(&event, start, format)?;
+                if device.is_some_and(|device| device.class() == topology::USB_CLASS_HUB) {
+                    let devices = device::list().await?;
+                    watch_hubs(&mut watched, &devices, &args, &sender).await;
+                }
+            }
+            Some(event) = receiver.recv() => print_event(&event, start, format)?,
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+/// Start watching the ports of hubs among `devices` that aren't already
+/// being watched, and stop watching hubs that have gone. Each hub is watched
+/// by its own task, which sends its events to `sender`.
+async fn watch_hubs(
+    watched: &mut HashMap<String, tokio::task::JoinHandle<()>>,
+    devices: &[Device],
+    args: &cli::WatchArgs,
+    sender: &tokio::sync::mpsc::UnboundedSender<Event>,
+) {
+    let hubs = topology::hub_infos(devices);
+    let locations: Vec<String> = hubs
+        .iter()
+        .map(|hub| Location::of(hub).to_string())
+        .collect();
+    watched.retain(|location, task| {
+        let present = locations.contains(location);
+        if !present {
+            task.abort();
+        }
+        present
+    });
+    for (index, (info, location)) in hubs.iter().zip(locations).enumerate() {
+        if watched.contains_key(&location) {
+            continue;
+        }
+        let hub = Hub::describe(index, info, devices).await;
+        let events = match HubEvents::open(&hub, args.interval).await {
+            Ok(events) => events,
+            Err(e) => {
+                log::debug!("Couldn't watch hub {}: {e}", hub.name());
+                continue;
+            }
         };
+        let task = tokio::spawn(watch_hub(
+            events,
+            hub.power_good_delay(),
+            args.recovery_policy(),
+            sender.clone(),
+        ));
+        watched.insert(location, task);
+    }
+}
 
-        let mut children = vec![];
-        if let Some(port_count) = port_count {
-            children.resize_with(port_count as usize, || "<no device>".to_owned());
-            let pc = device_info.port_chain();
-            for child_device in &devices {
-                if child_device.bus_id() != device_info.bus_id() {
-                    continue;
+/// Send the events of one hub to `sender` until the hub goes away. Ports
+/// that trip over-current are powered again if there's a policy for it,
+/// each in a task of its own so that events keep coming while it backs off.
+async fn watch_hub(
+    mut events: HubEvents,
+    power_good: Duration,
+    policy: Option<RecoveryPolicy>,
+    sender: tokio::sync::mpsc::UnboundedSender<Event>,
+) {
+    let hub = events.location().to_owned();
+    // Dropped along with this task, which stops any recovery still running.
+    let mut recoveries = tokio::task::JoinSet::new();
+    let mut recovering: HashMap<u8, tokio::task::AbortHandle> = HashMap::new();
+    loop {
+        while recoveries.try_join_next().is_some() {}
+        recovering.retain(|_, task| !task.is_finished());
+        let changes = match events.next().await {
+            Ok(changes) => changes,
+            Err(e) => {
+                log::debug!("Stopped watching hub {hub}: {e}");
+                return;
+            }
+        };
+        for change in changes {
+            if let Some(event) = Event::from_change(&hub, &change) {
+                let _ = sender.send(event);
+            }
+            let Some(policy) = policy else {
+                continue;
+            };
+            let AnyChange::Port(change) = change else {
+                continue;
+            };
+            let port = change.port;
+            if change.event != PortEvent::OverCurrent || recovering.contains_key(&port) {
+                continue;
+            }
+            let control = events.control().clone();
+            let hub = hub.clone();
+            let sender = sender.clone();
+            let task = recoveries.spawn(async move {
+                let (recovered, attempts) =
+                    match recovery::recover(&control, port, power_good, &policy).await {
+                        Ok(attempts) => (true, attempts),
+                        Err(e) => {
+                            log::warn!("Couldn't power {hub} port {port} again: {e}");
+                            (false, policy.attempts())
+                        }
+                    };
+                let _ = sender.send(Event::Recovery {
+                    hub,
+                    port,
+                    recovered,
+                    attempts,
+                });
+            });
+            recovering.insert(port, task);
+        }
+    }
+}
+
+async fn run(command: cli::Command, verify: bool, format: cli::Format) -> eyre::Result<()> {
+    match command {
+        cli::Command::List => {
+            let devices = device::list().await?;
+            let hubs = topology::discover(&devices).await;
+            match format {
+                cli::Format::Text => {
+                    for hub in &hubs {
+                        print!("{}: {hub}", hub.index());
+                        if let Some(other) = companion(hub, &hubs) {
+                            println!("    Other half of this USB 3 hub: {}", other.index());
+                        }
+                    }
+                }
+                cli::Format::Json => {
+                    let mut reports = vec![];
+                    for hub in &hubs {
+                        let mut report = report::Hub::read(hub, None).await;
+                        report.companion = companion(hub, &hubs)
+                            .map(|other| Location::of(other.info()).to_string());
+                        reports.push(report);
+                    }
+                    print_json(&reports)?;
+                }
+                cli::Format::Dot | cli::Format::Mermaid => {
+                    return Err(format.unsupported("list").into());
                 }
-                let cpc = child_device.port_chain();
-                if cpc.len() != pc.len() + 1 {
-                    continue;
+            }
+        }
+        cli::Command::Topology => {
+            let render: fn(&Tree) -> String = match format {
+                cli::Format::Text => |tree| tree.to_string(),
+                cli::Format::Dot => graph::dot,
+                cli::Format::Mermaid => graph::mermaid,
+                cli::Format::Json => return Err(format.unsupported("topology").into()),
+            };
+            let devices = device::list().await?;
+            print!("{}", render(&Tree::read(&devices).await));
+        }
+        cli::Command::Status(args) => {
+            let (hubs, selected) = match args.target.resolve()? {
+                (Some(selector), port) => {
+                    let (hub, port) = topology::find(&selector, port.as_ref()).await?;
+                    (vec![(hub, port)], true)
                 }
-                if cpc[0..pc.len()] != *pc {
-                    continue;
+                (None, _) => {
+                    let devices = device::list().await?;
+                    let hubs = topology::discover(&devices)
+                        .await
+                        .into_iter()
+                        .map(|hub| (hub, None))
+                        .collect();
+                    (hubs, false)
                 }
-                let port_number = cpc[cpc.len() - 1];
-                if port_number == 0 {
-                    println!("ERROR: Port number is 0!");
-                    continue;
+            };
+            match format {
+                cli::Format::Text => {
+                    for (hub, port) in &hubs {
+                        print_status(hub.clone(), *port).await?;
+                    }
+                }
+                cli::Format::Json => {
+                    let all: Vec<Hub> = hubs.iter().map(|(hub, _)| hub.clone()).collect();
+                    let mut reports = vec![];
+                    for (hub, port) in &hubs {
+                        let mut report = report::Hub::read(hub, *port).await;
+                        let other = match selected {
+                            true => find_companion(hub).await?,
+                            false => companion(hub, &all).cloned(),
+                        };
+                        report.companion =
+                            other.map(|other| Location::of(other.info()).to_string());
+                        reports.push(report);
+                    }
+                    print_json(&reports)?;
+                }
+                cli::Format::Dot | cli::Format::Mermaid => {
+                    return Err(format.unsupported("status").into());
                 }
-                let name = usb_ids::Device::from_vid_pid(
-                    child_device.vendor_id(),
-                    child_device.product_id(),
-                )
-                .map(|v| v.name().to_owned())
-                .or_else(|| {
-                    child_device.product_string().and_then(|ps| {
-                        Some(format!(
-                            "{ps} from {}",
-                            usb_ids::Vendor::from_id(child_device.vendor_id())
-                                .map(|v| v.name())
-                                .unwrap_or("[unknown vendor]")
-                        ))
-                    })
-                })
-                .unwrap_or_else(|| "<unknown>".to_owned());
-                children[port_number as usize - 1] = name;
             }
-        } else {
-            println!("Can't inquire port count from hub");
+            if let Some(changes) = args.acknowledge {
+                for (hub, port) in &hubs {
+                    acknowledge_changes(hub, *port, &changes, format).await?;
+                }
+            }
+        }
+        cli::Command::Info(target) => {
+            if format != cli::Format::Text {
+                return Err(format.unsupported("info").into());
+            }
+            let (hub, _) = topology::find(&target.resolve_hub()?, None).await?;
+            print_info(&hub).await?;
+        }
+        cli::Command::On(args) => {
+            let target = open_port(&args).await?;
+            target.set_power(true, verify).await?;
+            println!("Turned port {} ON{}", target.port, target.also());
+        }
+        cli::Command::Off(args) => {
+            let target = open_port(&args).await?;
+            target.set_power(false, verify).await?;
+            println!("Turned port {} off{}", target.port, target.also());
+        }
+        cli::Command::Toggle(args) => {
+            let target = open_port(&args).await?;
+            let port = target.port;
+            let enabled = !target.control.status(port).await?.powered();
+            target.set_power(enabled, verify).await?;
+            println!(
+                "Toggled port {port}{} {}",
+                target.also(),
+                target.control.status(port).await?
+            );
+        }
+        cli::Command::Cycle(args) => {
+            let target = open_port(&args.port).await?;
+            let port = target.port;
+            let power_good = target
+                .hubs()
+                .map(|(hub, _)| hub.power_good_delay())
+                .max()
+                .unwrap_or_default();
+            let off_time = args.off_time.unwrap_or(CYCLE_OFF_TIME.max(power_good));
+            if off_time < power_good {
+                log::warn!(
+                    "Off time of {off_time:?} is shorter than the hub's power-good time of {power_good:?}"
+                );
+            }
+
+            target.set_power(false, verify).await?;
+            tokio::time::sleep(off_time).await;
+            let watch = if args.wait {
+                Some(nusb::watch_devices()?)
+            } else {
+                None
+            };
+            target.set_power(true, verify).await?;
+            println!("Power cycled port {port}{}", target.also());
+
+            if let Some(watch) = watch {
+                let hubs: Vec<&Device> = target.hubs().map(|(hub, _)| hub.info()).collect();
+                let device = wait_for_device(&hubs, port, watch, args.timeout).await?;
+                println!(
+                    "Device {:04x}:{:04x} enumerated on port {port}",
+                    device.vendor_id(),
+                    device.product_id()
+                );
+            }
+        }
+        cli::Command::Reset(target) => {
+            let (control, port) = open_target(&target).await?;
+            let status = control.reset(port).await?;
+            println!("Reset port {port}: {status}");
+        }
+        cli::Command::Suspend(target) => {
+            let (control, port) = open_target(&target).await?;
+            let status = control.suspend(port).await?;
+            println!("Suspended port {port}: {status}");
+        }
+        cli::Command::Resume(target) => {
+            let (control, port) = open_target(&target).await?;
+            let status = control.resume(port).await?;
+            println!("Resumed port {port}: {status}");
+        }
+        cli::Command::Identify(args) => {
+            let (selector, port) = args.target.resolve_port()?;
+            let (hub, port) = topology::find(&selector, Some(&port)).await?;
+            let port = port.expect("port was requested");
+            if !hub
+                .descriptor()
+                .is_some_and(|descriptor| descriptor.port_indicators())
+            {
+                let caveat = format!("hub {} doesn't have port indicators", hub.name());
+                if !args.force {
+                    return Err(RefusedError(caveat).into());
+                }
+                eprintln!("Warning: {caveat}");
+            }
+            let control = HubControl::new(hub.info()).await?;
+            println!(
+                "Blinking the indicator of port {port} for {:?}",
+                args.duration
+            );
+            tokio::select! {
+                result = indicator::identify(&control, port, args.duration) => result?,
+                _ = tokio::signal::ctrl_c() => {
+                    control.set_indicator(port, Indicator::Automatic).await?;
+                }
+            }
+        }
+        cli::Command::Link(args) => {
+            let (control, port) = open_target(&args.target).await?;
+            let status = control.set_link_state(port, args.to).await?;
+            println!("Moved the link of port {port} to {}: {status}", args.to);
         }
+        cli::Command::Watch(args) => watch_ports(args, format).await?,
+    }
+    Ok(())
+}
 
-        choices.push(SelectableDevice {
-            name,
-            info: device_info.clone(),
-            children,
-        });
+/// A hub in the interactive menu, indented below the hub it's plugged into.
+struct HubChoice {
+    depth: usize,
+    hub: Hub,
+    label: String,
+}
+
+impl HubChoice {
+    fn new(depth: usize, node: &HubNode) -> Self {
+        HubChoice {
+            depth,
+            hub: node.hub().clone(),
+            label: node.label(),
+        }
     }
+}
 
-    let selection = inquire::Select::new("Select a hub", choices).prompt()?;
-    let mut hub = TogglableDevice::new(selection).await?;
+impl core::fmt::Display for HubChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.depth {
+            0 => write!(f, "{}", self.label),
+            depth => write!(
+                f,
+                "{:indent$}|__ {}",
+                "",
+                self.label,
+                indent = (depth - 1) * 4
+            ),
+        }
+    }
+}
 
+/// Pick a hub from the tree, then toggle its ports. Backing out of the port
+/// menu goes back to the tree, which is read again to pick up any changes.
+async fn interactive(verify: bool) -> eyre::Result<()> {
+    let mut cursor = 0;
+    loop {
+        let devices = device::list().await?;
+        let choices: Vec<HubChoice> = Tree::read(&devices)
+            .await
+            .hubs()
+            .into_iter()
+            .map(|(depth, node)| HubChoice::new(depth, node))
+            .collect();
+        if choices.is_empty() {
+            println!("No hubs found");
+            return Ok(());
+        }
+        let Ok(selection) = inquire::Select::new("Select a hub", choices)
+            .with_starting_cursor(cursor)
+            .raw_prompt()
+        else {
+            break;
+        };
+        cursor = selection.index;
+        match TogglableDevice::new(selection.value.hub).await {
+            Ok(hub) => toggle_ports(hub, verify).await,
+            Err(e) => println!("Couldn't open hub: {e}"),
+        }
+    }
+    println!("Done");
+    Ok(())
+}
+
+/// Toggle ports of one hub until the menu is dismissed.
+async fn toggle_ports(mut hub: TogglableDevice, verify: bool) {
     let mut index = 0;
     while let Ok(port) = inquire::Select::new("Select a port to toggle", hub.selection())
         .with_starting_cursor(index)
         .prompt()
     {
         index = port.index as usize - 1;
-        if let Err(e) = hub.toggle(port.index).await {
-            println!("Couldn't toggle port {}: {e}", port.index);
-        } else {
-            println!(
+        if let Some(caveat) = hub
+            .hub
+            .descriptor()
+            .and_then(|descriptor| power_switching_caveat(descriptor, port.index))
+        {
+            let confirmed = inquire::Confirm::new(&format!("Warning: {caveat}. Continue?"))
+                .with_default(false)
+                .prompt();
+            if !matches!(confirmed, Ok(true)) {
+                continue;
+            }
+        }
+        match hub.toggle(port.index, verify).await {
+            Ok(enabled) => println!(
                 "Toggled port {} {}",
                 port.index,
-                if port.enabled { "off" } else { "ON" }
-            );
+                if enabled { "ON" } else { "off" }
+            ),
+            Err(e) => println!("Couldn't toggle port {}: {e}", port.index),
+        }
+    }
+}
+
+/// Map an error onto the process exit status so scripts can tell failures apart.
+fn exit_code(error: &eyre::Report) -> ExitCode {
+    if error.downcast_ref::<clap::Error>().is_some() {
+        return ExitCode::from(EXIT_USAGE);
+    }
+    if error.downcast_ref::<RefusedError>().is_some() {
+        return ExitCode::from(EXIT_REFUSED);
+    }
+    match error.downcast_ref::<Error>() {
+        Some(Error::Lookup(_)) => ExitCode::from(EXIT_NOT_FOUND),
+        Some(Error::Ignored(_)) => ExitCode::from(EXIT_IGNORED),
+        Some(Error::Timeout(_)) => ExitCode::from(EXIT_TIMEOUT),
+        Some(Error::OverCurrent(_)) => ExitCode::from(EXIT_OVER_CURRENT),
+        Some(Error::Unsupported(_)) => ExitCode::from(EXIT_REFUSED),
+        Some(Error::Usb(_) | Error::Transfer(_) | Error::Descriptor(_)) => {
+            ExitCode::from(EXIT_USB_ERROR)
+        }
+        None if error.downcast_ref::<nusb::Error>().is_some() => ExitCode::from(EXIT_USB_ERROR),
+        _ => ExitCode::FAILURE,
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    env_logger::init();
+    let cli = cli::Cli::parse();
+    if let Some(path) = &cli.record {
+        match Recording::create(path) {
+            Ok(recording) => {
+                record::record_session(recording);
+            }
+            Err(e) => {
+                eprintln!("Error: couldn't create {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if let Some(path) = &cli.replay {
+        if let Err(e) = cli.check_replay() {
+            e.exit();
+        }
+        match ReplaySession::load(path) {
+            Ok(session) => {
+                record::replay_session(session);
+            }
+            Err(e) => {
+                eprintln!("Error: couldn't load {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let result = match cli.command {
+        Some(command) => run(command, cli.verify, cli.format).await,
+        None => interactive(cli.verify).await,
+    };
+    // A replay that went differently from the recording is a failure even if
+    // the command got through it.
+    let result = result.and_then(|()| match record::replay() {
+        Some(replay) => Ok(replay.finish()?),
+        None => Ok(()),
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            if let Some(usage) = e.downcast_ref::<clap::Error>() {
+                let _ = usage.print();
+            } else {
+                eprintln!("Error: {e:?}");
+            }
+            exit_code(&e)
         }
     }
-    println!("Done");
-    Ok(())
 }
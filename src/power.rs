@@ -0,0 +1,164 @@
+//! Checking that power changes did what was asked of them.
+
+use std::time::Duration;
+
+use futures_lite::StreamExt;
+use nusb::{
+    DeviceInfo,
+    hotplug::{HotplugEvent, HotplugWatch},
+};
+
+use crate::control::HubControl;
+use crate::descriptor::{AnyHubDescriptor, PowerSwitching};
+use crate::device::{self, Device};
+use crate::error::{Error, IgnoredError, OverCurrentError, TimeoutError};
+use crate::selector::hub_port_of;
+use crate::status::PortStatus;
+use crate::topology::Hub;
+use crate::transport::Transport;
+
+/// How long to wait for a device to disappear after its port is turned off.
+pub const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Check that a port really did change to the power state `enabled` once the
+/// hub's power-on-to-power-good time has passed. When turning a port off,
+/// the attached device must also have gone away.
+pub async fn verify_power<T: Transport>(
+    control: &HubControl<T>,
+    hub: &Hub,
+    port: u8,
+    enabled: bool,
+) -> Result<PortStatus, Error> {
+    tokio::time::sleep(hub.power_good_delay()).await;
+    let status = control.status(port).await?;
+    if enabled && status.over_current() {
+        return Err(OverCurrentError { port, attempts: 0 }.into());
+    }
+    if status.powered() != enabled {
+        return Err(IgnoredError {
+            port,
+            reason: "port power state didn't change",
+        }
+        .into());
+    }
+    if enabled {
+        return Ok(status);
+    }
+    if status.connected() {
+        return Err(IgnoredError {
+            port,
+            reason: "device is still connected",
+        }
+        .into());
+    }
+
+    let deadline = tokio::time::Instant::now() + DISCONNECT_TIMEOUT;
+    loop {
+        let present = device::list()
+            .await?
+            .iter()
+            .any(|child| hub_port_of(hub.info(), child) == Some(port));
+        if !present {
+            return Ok(status);
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(IgnoredError {
+                port,
+                reason: "device is still enumerated",
+            }
+            .into());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Wait for a device to be connected to `port` of any of `hubs`, such as
+/// both halves of a USB 3 hub. The watch should be created before the port
+/// is powered so that the event can't be missed.
+pub async fn wait_for_device(
+    hubs: &[&Device],
+    port: u8,
+    mut watch: HotplugWatch,
+    timeout: Duration,
+) -> Result<DeviceInfo, Error> {
+    let wait = async {
+        while let Some(event) = watch.next().await {
+            log::debug!("Hotplug event: {event:?}");
+            match event {
+                HotplugEvent::Connected(device)
+                    if hubs
+                        .iter()
+                        .any(|hub| hub_port_of(*hub, &device) == Some(port)) =>
+                {
+                    return device;
+                }
+                _ => {}
+            }
+        }
+        log::warn!("Hotplug event stream ended");
+        std::future::pending().await
+    };
+    tokio::time::timeout(timeout, wait).await.map_err(|_| {
+        TimeoutError {
+            port,
+            timeout,
+            waiting_for: "a device to appear",
+        }
+        .into()
+    })
+}
+
+/// Describe how switching power to `port` would do something other than
+/// what was asked, based on the hub's power switching mode.
+pub fn power_switching_caveat(descriptor: &AnyHubDescriptor, port: u8) -> Option<String> {
+    match descriptor.characteristics().power_switching() {
+        PowerSwitching::Individual => None,
+        PowerSwitching::Ganged => {
+            let others: Vec<String> = (1..=descriptor.port_count())
+                .filter(|other| *other != port)
+                .map(|other| other.to_string())
+                .collect();
+            if others.is_empty() {
+                return None;
+            }
+            Some(format!(
+                "hub uses ganged power switching, so this also affects {} {}",
+                if others.len() == 1 { "port" } else { "ports" },
+                others.join(", ")
+            ))
+        }
+        PowerSwitching::NoSwitching => Some(format!(
+            "hub doesn't support power switching, so port {port} will likely stay powered"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HubControl;
+    use crate::sim::SimHub;
+
+    async fn caveat(ports: u8, switching: PowerSwitching, port: u8) -> Option<String> {
+        let hub = SimHub::test_hub(ports).with_power_switching(switching);
+        let descriptor = HubControl::with_transport(hub, false)
+            .descriptor()
+            .await
+            .unwrap();
+        power_switching_caveat(&descriptor, port)
+    }
+
+    #[tokio::test]
+    async fn ganged_caveat_names_the_other_ports() {
+        assert_eq!(
+            caveat(4, PowerSwitching::Ganged, 2).await.as_deref(),
+            Some("hub uses ganged power switching, so this also affects ports 1, 3, 4")
+        );
+        assert_eq!(
+            caveat(2, PowerSwitching::Ganged, 2).await.as_deref(),
+            Some("hub uses ganged power switching, so this also affects port 1")
+        );
+        assert_eq!(caveat(1, PowerSwitching::Ganged, 1).await, None);
+        assert_eq!(caveat(4, PowerSwitching::Individual, 1).await, None);
+    }
+}
@@ -0,0 +1,674 @@
+//! Recording the control transfers sent to a hub, and replaying them later.
+//!
+//! A [`Recorder`] wraps a transport and logs every transfer it carries, along
+//! with the data or error that came back, to a [`Recording`]. Recordings are
+//! saved as JSON lines, one transfer per line, with a line for each time the
+//! devices were listed. A [`Replay`] reads them back and answers the same
+//! transfers in the same order, so that a session with a misbehaving hub can
+//! be reproduced without it. A [`ReplaySession`] does the same for a whole
+//! session of hubctl, with any number of hubs, by also answering for
+//! [`device::list`](crate::device::list).
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use nusb::transfer::{ControlIn, ControlOut, ControlType, Recipient, TransferError};
+use serde::{Deserialize, Serialize};
+
+use crate::device::Device;
+use crate::selector::Location;
+use crate::sim::SimDevice;
+use crate::transport::Transport;
+
+/// Direction bit of `bmRequestType` for device-to-host transfers.
+const DIRECTION_IN: u8 = 0x80;
+
+/// A single control transfer and its outcome.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Exchange {
+    /// Where the hub the transfer was sent to is plugged in, such as `1-2`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hub: Option<String>,
+    /// `bmRequestType`, which holds the direction, type and recipient
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+    /// Data sent with a ControlOut
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "hex")]
+    pub sent: Vec<u8>,
+    /// Data returned by a ControlIn
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "hex")]
+    pub received: Vec<u8>,
+    /// How the transfer failed, such as `Stall`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Exchange {
+    fn control_in(data: &ControlIn) -> Self {
+        Exchange {
+            hub: None,
+            request_type: DIRECTION_IN | request_type(data.control_type, data.recipient),
+            request: data.request,
+            value: data.value,
+            index: data.index,
+            length: data.length,
+            sent: vec![],
+            received: vec![],
+            error: None,
+        }
+    }
+
+    fn control_out(data: &ControlOut<'_>) -> Self {
+        Exchange {
+            hub: None,
+            request_type: request_type(data.control_type, data.recipient),
+            request: data.request,
+            value: data.value,
+            index: data.index,
+            length: data.data.len() as u16,
+            sent: data.data.to_vec(),
+            received: vec![],
+            error: None,
+        }
+    }
+
+    /// Whether `other` is the same request, ignoring its outcome.
+    fn same_request(&self, other: &Exchange) -> bool {
+        self.request_type == other.request_type
+            && self.request == other.request
+            && self.value == other.value
+            && self.index == other.index
+            && self.length == other.length
+            && self.sent == other.sent
+    }
+
+    fn outcome(&self) -> Result<Vec<u8>, TransferError> {
+        match &self.error {
+            Some(error) => Err(parse_transfer_error(error)),
+            None => Ok(self.received.clone()),
+        }
+    }
+}
+
+impl core::fmt::Display for Exchange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "request type {:#04x} request {} value {:#06x} index {} length {}",
+            self.request_type, self.request, self.value, self.index, self.length
+        )
+    }
+}
+
+fn request_type(control_type: ControlType, recipient: Recipient) -> u8 {
+    (control_type as u8) << 5 | recipient as u8
+}
+
+/// Turn the name an error was recorded under back into the error.
+fn parse_transfer_error(name: &str) -> TransferError {
+    match name {
+        "Cancelled" => TransferError::Cancelled,
+        "Stall" => TransferError::Stall,
+        "Disconnected" => TransferError::Disconnected,
+        "Fault" => TransferError::Fault,
+        "InvalidArgument" => TransferError::InvalidArgument,
+        other => TransferError::Unknown(
+            other
+                .strip_prefix("Unknown(")
+                .and_then(|code| code.strip_suffix(')'))
+                .and_then(|code| code.parse().ok())
+                .unwrap_or(0),
+        ),
+    }
+}
+
+/// Byte strings are written as hex to keep recordings readable.
+mod hex {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: String = data.iter().map(|byte| format!("{byte:02x}")).collect();
+        serializer.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if hex.len() % 2 != 0 {
+            return Err(D::Error::custom("odd number of hex digits"));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+/// A line of a recording.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    /// The devices on the system, each time they were listed.
+    Devices {
+        devices: Vec<SimDevice>,
+    },
+    Exchange(Exchange),
+}
+
+/// Read every line of a recording saved by [`Recording::create`].
+fn read_entries(path: impl AsRef<Path>) -> Result<Vec<Entry>, ReplayError> {
+    let mut entries = vec![];
+    for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(serde_json::from_str(&line).map_err(|e| ReplayError::Parse(index + 1, e))?);
+    }
+    Ok(entries)
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    exchanges: Vec<Exchange>,
+    file: Option<File>,
+}
+
+/// Where a [`Recorder`] puts the transfers it sees. Clones share the same
+/// log, so several hubs can be recorded into one file.
+#[derive(Debug, Clone, Default)]
+pub struct Recording(Arc<Mutex<Log>>);
+
+impl Recording {
+    /// A recording kept only in memory.
+    pub fn new() -> Self {
+        Recording::default()
+    }
+
+    /// A recording that's also written to `path` as it happens, so that it
+    /// survives the program crashing.
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Recording(Arc::new(Mutex::new(Log {
+            exchanges: vec![],
+            file: Some(File::create(path)?),
+        }))))
+    }
+
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        self.0.lock().unwrap().exchanges.clone()
+    }
+
+    fn push(&self, exchange: Exchange) {
+        let mut log = self.0.lock().unwrap();
+        log.write(&exchange);
+        log.exchanges.push(exchange);
+    }
+
+    /// Note the devices that were just listed, so that a replay can list
+    /// the same ones.
+    pub fn push_devices(&self, devices: Vec<SimDevice>) {
+        self.0.lock().unwrap().write(&Entry::Devices { devices });
+    }
+}
+
+impl Log {
+    fn write(&mut self, entry: &impl Serialize) {
+        if let Some(file) = &mut self.file {
+            let line = serde_json::to_string(entry).expect("entries always serialize");
+            if let Err(e) = writeln!(file, "{line}") {
+                log::error!("Couldn't write to recording: {e}");
+            }
+        }
+    }
+}
+
+static SESSION: OnceLock<Recording> = OnceLock::new();
+
+/// Record every transfer made by hubs opened with
+/// [`HubControl::new`](crate::HubControl::new) from now on. Returns `false`
+/// if a session recording had already been started.
+pub fn record_session(recording: Recording) -> bool {
+    SESSION.set(recording).is_ok()
+}
+
+/// The recording started by [`record_session`], if any.
+pub fn session() -> Option<Recording> {
+    SESSION.get().cloned()
+}
+
+/// A transport that logs each transfer to a [`Recording`] before handing
+/// back the result. With no recording, it passes transfers straight through.
+#[derive(Debug, Clone)]
+pub struct Recorder<T> {
+    inner: T,
+    recording: Option<Recording>,
+    hub: Option<String>,
+}
+
+impl<T> Recorder<T> {
+    pub fn new(inner: T, recording: Option<Recording>) -> Self {
+        Recorder {
+            inner,
+            recording,
+            hub: None,
+        }
+    }
+
+    /// The transport the transfers are passed on to.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Tag each transfer with the location of the hub it was sent to, so
+    /// that the transfers of several hubs can be told apart on replay.
+    pub fn with_hub(mut self, location: String) -> Self {
+        self.hub = Some(location);
+        self
+    }
+}
+
+impl<T: Transport + Sync> Transport for Recorder<T> {
+    fn control_in(
+        &self,
+        data: ControlIn,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Vec<u8>, TransferError>> + Send {
+        let mut exchange = Exchange {
+            hub: self.hub.clone(),
+            ..Exchange::control_in(&data)
+        };
+        async move {
+            let result = self.inner.control_in(data, timeout).await;
+            if let Some(recording) = &self.recording {
+                match &result {
+                    Ok(received) => exchange.received = received.clone(),
+                    Err(e) => exchange.error = Some(format!("{e:?}")),
+                }
+                recording.push(exchange);
+            }
+            result
+        }
+    }
+
+    fn control_out(
+        &self,
+        data: ControlOut<'_>,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<(), TransferError>> + Send {
+        let mut exchange = Exchange {
+            hub: self.hub.clone(),
+            ..Exchange::control_out(&data)
+        };
+        async move {
+            let result = self.inner.control_out(data, timeout).await;
+            if let Some(recording) = &self.recording {
+                if let Err(e) = &result {
+                    exchange.error = Some(format!("{e:?}"));
+                }
+                recording.push(exchange);
+            }
+            result
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    /// A line of the recording couldn't be parsed.
+    Parse(usize, serde_json::Error),
+    /// The session asked for something other than what was recorded.
+    Diverged {
+        expected: Option<Box<Exchange>>,
+        actual: Box<Exchange>,
+    },
+    /// The session ended before using every recorded transfer.
+    Unused(usize),
+    /// The session ended before listing the devices as often as recorded.
+    UnusedListings(usize),
+    /// Replaying the transfers of the hub at this location went wrong.
+    Hub(String, Box<ReplayError>),
+}
+
+impl core::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "{e}"),
+            ReplayError::Parse(line, e) => write!(f, "line {line} of recording: {e}"),
+            ReplayError::Diverged {
+                expected: Some(expected),
+                actual,
+            } => write!(f, "expected {expected}, got {actual}"),
+            ReplayError::Diverged {
+                expected: None,
+                actual,
+            } => write!(f, "recording ended, got {actual}"),
+            ReplayError::Unused(count) => write!(f, "{count} recorded transfers weren't used"),
+            ReplayError::UnusedListings(count) => {
+                write!(f, "{count} recorded device listings weren't used")
+            }
+            ReplayError::Hub(location, e) => write!(f, "hub {location}: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(value: std::io::Error) -> Self {
+        ReplayError::Io(value)
+    }
+}
+
+#[derive(Debug, Default)]
+struct ReplayState {
+    remaining: VecDeque<Exchange>,
+    diverged: Option<ReplayError>,
+}
+
+/// A transport that answers transfers from a recording, in the order they
+/// were recorded. Once a transfer doesn't match the recording, it and every
+/// later transfer fail with [`TransferError::InvalidArgument`], and
+/// [`Replay::finish`] reports where things went wrong. Clones share the same
+/// position in the recording.
+#[derive(Debug, Clone)]
+pub struct Replay(Arc<Mutex<ReplayState>>);
+
+impl Replay {
+    pub fn new(exchanges: impl IntoIterator<Item = Exchange>) -> Self {
+        Replay(Arc::new(Mutex::new(ReplayState {
+            remaining: exchanges.into_iter().collect(),
+            diverged: None,
+        })))
+    }
+
+    /// Load a recording saved by [`Recording::create`], whichever hubs its
+    /// transfers were sent to. [`ReplaySession`] keeps the hubs apart.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        Ok(Replay::new(read_entries(path)?.into_iter().filter_map(
+            |entry| match entry {
+                Entry::Exchange(exchange) => Some(exchange),
+                Entry::Devices { .. } => None,
+            },
+        )))
+    }
+
+    /// Check that the session matched the recording and used all of it.
+    pub fn finish(&self) -> Result<(), ReplayError> {
+        let mut state = self.0.lock().unwrap();
+        if let Some(error) = state.diverged.take() {
+            return Err(error);
+        }
+        match state.remaining.len() {
+            0 => Ok(()),
+            count => Err(ReplayError::Unused(count)),
+        }
+    }
+
+    fn answer(&self, actual: Exchange) -> Result<Vec<u8>, TransferError> {
+        let mut state = self.0.lock().unwrap();
+        if state.diverged.is_some() {
+            return Err(TransferError::InvalidArgument);
+        }
+        match state.remaining.pop_front() {
+            Some(expected) if expected.same_request(&actual) => expected.outcome(),
+            expected => {
+                log::error!("Replay diverged from recording at {actual}");
+                state.diverged = Some(ReplayError::Diverged {
+                    expected: expected.map(Box::new),
+                    actual: Box::new(actual),
+                });
+                Err(TransferError::InvalidArgument)
+            }
+        }
+    }
+}
+
+impl Transport for Replay {
+    fn control_in(
+        &self,
+        data: ControlIn,
+        _timeout: Duration,
+    ) -> impl Future<Output = Result<Vec<u8>, TransferError>> + Send {
+        std::future::ready(self.answer(Exchange::control_in(&data)))
+    }
+
+    fn control_out(
+        &self,
+        data: ControlOut<'_>,
+        _timeout: Duration,
+    ) -> impl Future<Output = Result<(), TransferError>> + Send {
+        std::future::ready(self.answer(Exchange::control_out(&data)).map(|_| ()))
+    }
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    listings: VecDeque<Vec<SimDevice>>,
+    /// The last listing handed out, which is repeated once they run out.
+    last: Vec<SimDevice>,
+    hubs: HashMap<String, Replay>,
+}
+
+/// A recorded session of hubctl played back:
+/// [`device::list`](crate::device::list) returns the devices that were
+/// listed, in the order they were listed, and each hub answers the transfers
+/// that were sent to it. Clones share the same position in the recording.
+#[derive(Debug, Clone, Default)]
+pub struct ReplaySession(Arc<Mutex<SessionState>>);
+
+impl ReplaySession {
+    /// Load a recording saved by [`Recording::create`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        let mut listings = VecDeque::new();
+        let mut hubs: HashMap<String, Vec<Exchange>> = HashMap::new();
+        for entry in read_entries(path)? {
+            match entry {
+                Entry::Devices { devices } => listings.push_back(devices),
+                Entry::Exchange(exchange) => hubs
+                    .entry(exchange.hub.clone().unwrap_or_default())
+                    .or_default()
+                    .push(exchange),
+            }
+        }
+        Ok(ReplaySession(Arc::new(Mutex::new(SessionState {
+            listings,
+            last: vec![],
+            hubs: hubs
+                .into_iter()
+                .map(|(hub, exchanges)| (hub, Replay::new(exchanges)))
+                .collect(),
+        }))))
+    }
+
+    /// The next list of devices from the recording, each with the replay
+    /// of the transfers sent to it.
+    pub fn devices(&self) -> Vec<Device> {
+        let mut state = self.0.lock().unwrap();
+        if let Some(listing) = state.listings.pop_front() {
+            state.last = listing;
+        }
+        let listing = state.last.clone();
+        listing
+            .into_iter()
+            .map(|device| {
+                let replay = state
+                    .hubs
+                    .entry(Location::of(&device).to_string())
+                    .or_insert_with(|| Replay::new([]))
+                    .clone();
+                Device::Replayed(device, replay)
+            })
+            .collect()
+    }
+
+    /// Check that the session matched the recording for every hub, and used
+    /// all of it.
+    pub fn finish(&self) -> Result<(), ReplayError> {
+        let state = self.0.lock().unwrap();
+        let mut hubs: Vec<_> = state.hubs.iter().collect();
+        hubs.sort_by_key(|(location, _)| location.as_str());
+        for (location, replay) in hubs {
+            replay
+                .finish()
+                .map_err(|e| ReplayError::Hub(location.clone(), Box::new(e)))?;
+        }
+        match state.listings.len() {
+            0 => Ok(()),
+            count => Err(ReplayError::UnusedListings(count)),
+        }
+    }
+}
+
+static REPLAY: OnceLock<ReplaySession> = OnceLock::new();
+
+/// Answer [`device::list`](crate::device::list), and every transfer made by
+/// the hubs it returns, from `session` from now on instead of the devices on
+/// the system. Returns `false` if a session was already being replayed.
+pub fn replay_session(session: ReplaySession) -> bool {
+    REPLAY.set(session).is_ok()
+}
+
+/// The session started by [`replay_session`], if any.
+pub fn replay() -> Option<ReplaySession> {
+    REPLAY.get().cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HubControl;
+    use crate::descriptor::PowerSwitching;
+    use crate::device::UsbDevice;
+    use crate::error::Error;
+    use crate::sim::{SimDevice, SimHub};
+
+    /// Run a short session against a simulated hub, recording it.
+    async fn record() -> Vec<Exchange> {
+        let hub = SimHub::test_hub(2);
+        hub.attach(1, SimDevice::new(0x1366, 0x0105));
+        let recording = Recording::new();
+        let control =
+            HubControl::with_transport(Recorder::new(hub, Some(recording.clone())), false);
+        control.descriptor().await.unwrap();
+        assert!(!control.toggle(1).await.unwrap());
+        assert!(control.status(3).await.is_err());
+        recording.exchanges()
+    }
+
+    #[tokio::test]
+    async fn replay_reproduces_session() {
+        let exchanges = record().await;
+        assert_eq!(exchanges.len(), 4);
+        assert_eq!(exchanges[2].sent, b"");
+        assert_eq!(exchanges[3].error.as_deref(), Some("Stall"));
+
+        let replay = Replay::new(exchanges);
+        let control = HubControl::with_transport(replay.clone(), false);
+        assert_eq!(control.descriptor().await.unwrap().port_count(), 2);
+        assert!(!control.toggle(1).await.unwrap());
+        assert!(matches!(
+            control.status(3).await,
+            Err(Error::Transfer(TransferError::Stall))
+        ));
+        replay.finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_detects_divergence() {
+        let replay = Replay::new(record().await);
+        let control = HubControl::with_transport(replay.clone(), false);
+        control.descriptor().await.unwrap();
+        assert!(control.status(2).await.is_err());
+        assert!(control.status(1).await.is_err());
+        assert!(matches!(
+            replay.finish(),
+            Err(ReplayError::Diverged {
+                expected: Some(_),
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn replay_detects_unused_transfers() {
+        let replay = Replay::new(record().await);
+        HubControl::with_transport(replay.clone(), false)
+            .descriptor()
+            .await
+            .unwrap();
+        assert!(matches!(replay.finish(), Err(ReplayError::Unused(3))));
+    }
+
+    #[tokio::test]
+    async fn recordings_survive_a_file() {
+        let exchanges = record().await;
+        let path = std::env::temp_dir().join(format!("hubctl-record-{}.jsonl", std::process::id()));
+        let recording = Recording::create(&path).unwrap();
+        for exchange in &exchanges {
+            recording.push(exchange.clone());
+        }
+        drop(recording);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), exchanges.len());
+        assert!(text.contains(r#""error":"Stall""#));
+        let replay = Replay::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replay.0.lock().unwrap().remaining, exchanges);
+    }
+
+    #[tokio::test]
+    async fn sessions_keep_hubs_apart() {
+        let hubs = [
+            SimHub::test_hub(2),
+            SimHub::new(
+                SimDevice::hub("2", &[2], 0x0424, 0x5534).with_usb_version(0x0300),
+                2,
+                PowerSwitching::Individual,
+            ),
+        ];
+        let path =
+            std::env::temp_dir().join(format!("hubctl-session-{}.jsonl", std::process::id()));
+        let recording = Recording::create(&path).unwrap();
+        recording.push_devices(hubs.iter().map(|hub| hub.info().clone()).collect());
+        let controls = hubs.clone().map(|hub| {
+            let location = Location::of(hub.info()).to_string();
+            let superspeed = hub.info().is_superspeed();
+            let recorder = Recorder::new(hub, Some(recording.clone())).with_hub(location);
+            HubControl::with_transport(recorder, superspeed)
+        });
+        for control in &controls {
+            control.status(1).await.unwrap();
+        }
+        controls[1].off(2).await.unwrap();
+        drop(recording);
+
+        let session = ReplaySession::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let devices = session.devices();
+        assert_eq!(devices.len(), 2);
+        // Each hub only has to keep to its own order.
+        let usb3 = HubControl::new(&devices[1]).await.unwrap();
+        assert!(usb3.status(1).await.unwrap().powered());
+        usb3.off(2).await.unwrap();
+        let usb2 = HubControl::new(&devices[0]).await.unwrap();
+        assert!(usb2.status(1).await.unwrap().powered());
+        session.finish().unwrap();
+    }
+
+    #[test]
+    fn unknown_errors_keep_their_code() {
+        assert_eq!(
+            parse_transfer_error(&format!("{:?}", TransferError::Unknown(42))),
+            TransferError::Unknown(42)
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! Powering a port again after it trips over-current.
+//!
+//! A hub cuts power to a port that draws too much current and sets its
+//! C_PORT_OVER_CURRENT change bit, and the port stays off until the host
+//! turns it back on. [`recover`] does that according to a
+//! [`RecoveryPolicy`], backing off between attempts in case the fault is
+//! still there.
+
+use std::time::Duration;
+
+use crate::control::{HubControl, feature};
+use crate::error::{Error, OverCurrentError};
+use crate::transport::Transport;
+
+/// How many times to power a port again after over-current, and how long to
+/// wait first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryPolicy {
+    attempts: u32,
+    backoff: Duration,
+}
+
+impl RecoveryPolicy {
+    /// Try up to `attempts` times, waiting `backoff` before the first
+    /// attempt and twice as long before each one after that.
+    pub fn new(attempts: u32, backoff: Duration) -> Self {
+        RecoveryPolicy { attempts, backoff }
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// How long to wait before `attempt`, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        RecoveryPolicy::new(3, Duration::from_secs(1))
+    }
+}
+
+/// Clear the over-current change bit of `port` and power it again, until it
+/// stays powered or `policy` runs out of attempts. `power_good` is how long
+/// the hub takes to power a port. Returns the number of attempts it took.
+pub async fn recover<T: Transport>(
+    control: &HubControl<T>,
+    port: u8,
+    power_good: Duration,
+    policy: &RecoveryPolicy,
+) -> Result<u32, Error> {
+    for attempt in 1..=policy.attempts {
+        tokio::time::sleep(policy.delay(attempt)).await;
+        control
+            .port_feature(port, feature::C_PORT_OVER_CURRENT, false)
+            .await?;
+        if control.status(port).await?.over_current() {
+            log::warn!(
+                "Port {port} is still reporting over-current (attempt {attempt} of {})",
+                policy.attempts
+            );
+            continue;
+        }
+        control.on(port).await?;
+        tokio::time::sleep(power_good).await;
+        let status = control.status(port).await?;
+        if status.powered() && !status.over_current() && !status.over_current_changed() {
+            log::info!("Port {port} powered again after over-current (attempt {attempt})");
+            return Ok(attempt);
+        }
+        log::warn!(
+            "Port {port} tripped over-current again (attempt {attempt} of {})",
+            policy.attempts
+        );
+    }
+    Err(OverCurrentError {
+        port,
+        attempts: policy.attempts,
+    }
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimDevice, SimHub};
+
+    const POLICY: RecoveryPolicy = RecoveryPolicy {
+        attempts: 3,
+        backoff: Duration::from_millis(1),
+    };
+
+    fn hub() -> (SimHub, HubControl<SimHub>) {
+        let sim = SimHub::test_hub(4);
+        sim.attach(2, SimDevice::new(0x1366, 0x0105));
+        let control = HubControl::with_transport(sim.clone(), false);
+        (sim, control)
+    }
+
+    #[test]
+    fn backoff_doubles() {
+        let policy = RecoveryPolicy::new(4, Duration::from_millis(500));
+        let delays: Vec<Duration> = (1..=4).map(|attempt| policy.delay(attempt)).collect();
+        assert_eq!(delays, [500, 1000, 2000, 4000].map(Duration::from_millis));
+        assert!(policy.delay(100) > Duration::from_secs(86400));
+    }
+
+    #[tokio::test]
+    async fn transient_over_current_recovers() {
+        let (sim, control) = hub();
+        sim.set_over_current(2, true);
+        sim.set_over_current(2, false);
+        let status = control.status(2).await.unwrap();
+        assert!(!status.powered() && status.over_current_changed());
+
+        let attempts = recover(&control, 2, Duration::ZERO, &POLICY).await.unwrap();
+        assert_eq!(attempts, 1);
+        let status = control.status(2).await.unwrap();
+        assert!(status.powered() && status.connected());
+        assert!(!status.over_current_changed());
+    }
+
+    #[tokio::test]
+    async fn persistent_over_current_gives_up() {
+        let (sim, control) = hub();
+        sim.set_over_current(2, true);
+        let result = recover(&control, 2, Duration::ZERO, &POLICY).await;
+        assert!(matches!(
+            result,
+            Err(Error::OverCurrent(OverCurrentError {
+                port: 2,
+                attempts: 3
+            }))
+        ));
+        assert!(!sim.is_powered(2));
+    }
+
+    #[tokio::test]
+    async fn hub_over_current_is_reported() {
+        let (sim, control) = hub();
+        assert!(!control.hub_status().await.unwrap().over_current());
+        sim.set_hub_over_current(true);
+        let status = control.hub_status().await.unwrap();
+        assert!(status.over_current() && status.over_current_changed());
+        assert!(!status.local_power_lost());
+        assert_eq!(
+            status.to_string(),
+            "local power good, OVER-CURRENT (changed since last acknowledged: over-current)"
+        );
+    }
+}
@@ -0,0 +1,355 @@
+//! The machine-readable form of `hubctl list` and `hubctl status`, printed
+//! with `--format json`. The schema is documented in the README. Fields may
+//! be added in future, but existing ones keep their names and meaning.
+
+use serde::Serialize;
+use usb_ids::FromId;
+
+use crate::control::HubControl;
+use crate::descriptor::{AnyHubDescriptor, OverCurrentProtection, PowerSwitching};
+use crate::device::UsbDevice;
+use crate::selector::Location;
+use crate::status::{HubStatus, PortSpeed, PortStatus};
+use crate::topology;
+use crate::transport::Transport;
+
+/// A hub along with each of its ports.
+#[derive(Serialize)]
+pub struct Hub {
+    /// Position in the `hubctl list` output
+    pub index: usize,
+    #[serde(flatten)]
+    pub device: Device,
+    pub superspeed: bool,
+    /// `None` if the hub couldn't be opened or its descriptor couldn't be read
+    pub descriptor: Option<Descriptor>,
+    /// `None` if the hub couldn't be asked for its status
+    pub status: Option<HubState>,
+    /// Where the other half of a USB 3 hub is, written as `--location`
+    /// takes it. Filled in by whoever knows about the other hubs.
+    pub companion: Option<String>,
+    pub ports: Vec<Port>,
+}
+
+impl Hub {
+    /// Gather everything known about a hub and the status of its ports,
+    /// optionally limited to a single port.
+    pub async fn read(hub: &topology::Hub, port: Option<u8>) -> Self {
+        let control = HubControl::new(hub.info())
+            .await
+            .inspect_err(|e| log::debug!("Couldn't open hub: {e}"))
+            .ok();
+        Hub::read_with(hub, control.as_ref(), port).await
+    }
+
+    /// Like [`Hub::read`], but through `control`, or with no status at all
+    /// if the hub couldn't be opened.
+    pub async fn read_with<D: UsbDevice, T: Transport>(
+        hub: &topology::Hub<D>,
+        control: Option<&HubControl<T>>,
+        port: Option<u8>,
+    ) -> Self {
+        let status = match control {
+            Some(control) => control.hub_status().await.ok(),
+            None => None,
+        };
+        let mut ports = vec![];
+        for (index, child) in hub.children().iter().enumerate() {
+            let number = index as u8 + 1;
+            if port.is_some_and(|port| port != number) {
+                continue;
+            }
+            let status = match control {
+                Some(control) => control.status(number).await.ok(),
+                None => None,
+            };
+            ports.push(Port {
+                port: number,
+                removable: hub
+                    .descriptor()
+                    .is_none_or(|descriptor| descriptor.removable(number)),
+                status: status.as_ref().map(Status::from),
+                device: child.as_ref().map(Device::from),
+            });
+        }
+        Hub {
+            index: hub.index(),
+            device: Device::from(hub.info()),
+            superspeed: hub.info().is_superspeed(),
+            descriptor: hub.descriptor().map(Descriptor::from),
+            status: status.as_ref().map(HubState::from),
+            companion: None,
+            ports,
+        }
+    }
+}
+
+/// Any USB device, either a hub or something attached to one.
+#[derive(Debug, Clone, Serialize)]
+pub struct Device {
+    pub bus_id: String,
+    pub port_chain: Vec<u8>,
+    /// `bus_id` and `port_chain` written as `--location` takes them
+    pub location: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+    /// Names from the usb-ids database
+    pub vendor_name: Option<&'static str>,
+    pub product_name: Option<&'static str>,
+}
+
+impl<D: UsbDevice> From<&D> for Device {
+    fn from(info: &D) -> Self {
+        Device {
+            bus_id: info.bus_id().to_owned(),
+            port_chain: info.port_chain().to_vec(),
+            location: Location::of(info).to_string(),
+            vendor_id: info.vendor_id(),
+            product_id: info.product_id(),
+            manufacturer: info.manufacturer_string().map(str::to_owned),
+            product: info.product_string().map(str::to_owned),
+            serial_number: info.serial_number().map(str::to_owned),
+            vendor_name: usb_ids::Vendor::from_id(info.vendor_id()).map(|v| v.name()),
+            product_name: usb_ids::Device::from_vid_pid(info.vendor_id(), info.product_id())
+                .map(|d| d.name()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Descriptor {
+    pub port_count: u8,
+    pub power_switching: PowerSwitching,
+    pub over_current_protection: OverCurrentProtection,
+    pub compound_device: bool,
+    pub power_good_ms: u128,
+    pub controller_current_ma: u16,
+}
+
+impl From<&AnyHubDescriptor> for Descriptor {
+    fn from(descriptor: &AnyHubDescriptor) -> Self {
+        let characteristics = descriptor.characteristics();
+        Descriptor {
+            port_count: descriptor.port_count(),
+            power_switching: characteristics.power_switching(),
+            over_current_protection: characteristics.over_current_protection(),
+            compound_device: characteristics.compound_device(),
+            power_good_ms: descriptor.power_good_delay().as_millis(),
+            controller_current_ma: descriptor.controller_current_ma(),
+        }
+    }
+}
+
+/// The decoded status of a hub as a whole.
+#[derive(Serialize)]
+pub struct HubState {
+    pub local_power_lost: bool,
+    pub over_current: bool,
+    /// Names of the change bits that are set, such as `C_HUB_LOCAL_POWER`
+    pub changes: Vec<&'static str>,
+}
+
+impl From<&HubStatus> for HubState {
+    fn from(status: &HubStatus) -> Self {
+        HubState {
+            local_power_lost: status.local_power_lost(),
+            over_current: status.over_current(),
+            changes: status.changes(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Port {
+    pub port: u8,
+    pub removable: bool,
+    /// `None` if the hub couldn't be asked for the port's status
+    pub status: Option<Status>,
+    /// The device attached to the port, if it has enumerated
+    pub device: Option<Device>,
+}
+
+/// A decoded port status.
+#[derive(Serialize)]
+pub struct Status {
+    pub powered: bool,
+    pub connected: bool,
+    pub enabled: bool,
+    pub suspended: bool,
+    pub over_current: bool,
+    pub resetting: bool,
+    /// One of `low`, `full`, `high` or `super`, if a device is connected
+    pub speed: Option<&'static str>,
+    /// The link state of a SuperSpeed port, such as `U0` or `SS.Disabled`
+    pub link_state: Option<String>,
+    pub test_mode: bool,
+    pub indicator_control: bool,
+    /// Names of the change bits that are set, such as `C_PORT_CONNECTION`
+    pub changes: Vec<&'static str>,
+}
+
+impl From<&PortStatus> for Status {
+    fn from(status: &PortStatus) -> Self {
+        Status {
+            powered: status.powered(),
+            connected: status.connected(),
+            enabled: status.enabled(),
+            suspended: status.suspended(),
+            over_current: status.over_current(),
+            resetting: status.resetting(),
+            speed: status.speed().map(|speed| match speed {
+                PortSpeed::Low => "low",
+                PortSpeed::Full => "full",
+                PortSpeed::High => "high",
+                PortSpeed::SuperSpeed(_) => "super",
+            }),
+            link_state: status.link_state().map(|state| state.to_string()),
+            test_mode: status.test_mode(),
+            indicator_control: status.indicator_control(),
+            changes: status.changes(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::sim::{SimDevice, SimHub};
+
+    /// The names usb-ids has for a device, which depend on the database.
+    fn names(vendor_id: u16, product_id: u16) -> (Value, Value) {
+        (
+            json!(usb_ids::Vendor::from_id(vendor_id).map(|v| v.name())),
+            json!(usb_ids::Device::from_vid_pid(vendor_id, product_id).map(|d| d.name())),
+        )
+    }
+
+    /// The report of a 2-port hub with a J-Link on port 1, as `status`
+    /// prints it with `port`, or without a hub that could be opened.
+    async fn read(port: Option<u8>, opened: bool) -> Value {
+        let sim = SimHub::test_hub(2);
+        sim.attach(
+            1,
+            SimDevice::new(0x1366, 0x0105)
+                .with_product("J-Link")
+                .with_serial_number("000123456"),
+        );
+        let hub = sim.describe(&sim.devices()).await;
+        let control = HubControl::with_transport(sim.clone(), false);
+        let mut report = Hub::read_with(&hub, opened.then_some(&control), port).await;
+        report.companion = Some("2-2".to_owned());
+        serde_json::to_value(&report).unwrap()
+    }
+
+    #[tokio::test]
+    async fn list_schema() {
+        let (hub_vendor, hub_product) = names(0x0424, 0x2514);
+        let (vendor, product) = names(0x1366, 0x0105);
+        assert_eq!(
+            read(None, true).await,
+            json!({
+                "index": 0,
+                "bus_id": "1",
+                "port_chain": [2],
+                "location": "1-2",
+                "vendor_id": 0x0424,
+                "product_id": 0x2514,
+                "manufacturer": null,
+                "product": null,
+                "serial_number": null,
+                "vendor_name": hub_vendor,
+                "product_name": hub_product,
+                "superspeed": false,
+                "companion": "2-2",
+                "descriptor": {
+                    "port_count": 2,
+                    "power_switching": "individual",
+                    "over_current_protection": "individual",
+                    "compound_device": false,
+                    "power_good_ms": 100,
+                    "controller_current_ma": 100
+                },
+                "status": {
+                    "local_power_lost": false,
+                    "over_current": false,
+                    "changes": []
+                },
+                "ports": [
+                    {
+                        "port": 1,
+                        "removable": true,
+                        "status": {
+                            "powered": true,
+                            "connected": true,
+                            "enabled": true,
+                            "suspended": false,
+                            "over_current": false,
+                            "resetting": false,
+                            "speed": "high",
+                            "link_state": null,
+                            "test_mode": false,
+                            "indicator_control": false,
+                            "changes": ["C_PORT_CONNECTION"]
+                        },
+                        "device": {
+                            "bus_id": "1",
+                            "port_chain": [2, 1],
+                            "location": "1-2.1",
+                            "vendor_id": 0x1366,
+                            "product_id": 0x0105,
+                            "manufacturer": null,
+                            "product": "J-Link",
+                            "serial_number": "000123456",
+                            "vendor_name": vendor,
+                            "product_name": product
+                        }
+                    },
+                    {
+                        "port": 2,
+                        "removable": true,
+                        "status": {
+                            "powered": true,
+                            "connected": false,
+                            "enabled": false,
+                            "suspended": false,
+                            "over_current": false,
+                            "resetting": false,
+                            "speed": null,
+                            "link_state": null,
+                            "test_mode": false,
+                            "indicator_control": false,
+                            "changes": []
+                        },
+                        "device": null
+                    }
+                ]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn status_schema() {
+        let report = read(Some(2), true).await;
+        let ports = report["ports"].as_array().unwrap();
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports[0]["port"], 2);
+        assert_eq!(ports[0]["device"], Value::Null);
+
+        // A hub that couldn't be opened still has every field, with nulls
+        // for what couldn't be read.
+        let report = read(Some(1), false).await;
+        assert_eq!(report["status"], Value::Null);
+        assert_eq!(report["descriptor"]["port_count"], 2);
+        let ports = report["ports"].as_array().unwrap();
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports[0]["port"], 1);
+        assert_eq!(ports[0]["removable"], true);
+        assert_eq!(ports[0]["status"], Value::Null);
+        assert_eq!(ports[0]["device"]["location"], "1-2.1");
+    }
+}
@@ -0,0 +1,659 @@
+//! Ways of picking out a hub without going through the interactive menu.
+
+use std::str::FromStr;
+
+use crate::device::UsbDevice;
+
+/// Where a device sits in the USB topology, written the way Linux names it
+/// in sysfs: `3-1.4` is port 4 of the hub on port 1 of bus 3's root hub.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    bus: String,
+    ports: Vec<u8>,
+}
+
+impl Location {
+    pub fn of(device: &impl UsbDevice) -> Self {
+        Location {
+            bus: device.bus_id().to_owned(),
+            ports: device.port_chain().to_vec(),
+        }
+    }
+
+    pub fn matches(&self, device: &impl UsbDevice) -> bool {
+        same_bus(&self.bus, device.bus_id()) && self.ports == device.port_chain()
+    }
+
+    /// The hub this location is below, and the port on it. `None` for a
+    /// root hub.
+    pub fn parent(&self) -> Option<(Location, u8)> {
+        let (port, ports) = self.ports.split_last()?;
+        Some((
+            Location {
+                bus: self.bus.clone(),
+                ports: ports.to_vec(),
+            },
+            *port,
+        ))
+    }
+}
+
+impl FromStr for Location {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (bus, ports) = match s.split_once('-') {
+            Some((bus, ports)) => (bus, Some(ports)),
+            None => (s, None),
+        };
+        if bus.is_empty() {
+            return Err(format!("missing bus in location {s:?}"));
+        }
+        let ports = ports
+            .map(|ports| {
+                ports
+                    .split('.')
+                    .map(|port| port.parse::<u8>())
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()
+            .map_err(|e| format!("invalid port in location {s:?}: {e}"))?
+            .unwrap_or_default();
+        Ok(Location {
+            bus: bus.to_owned(),
+            ports,
+        })
+    }
+}
+
+impl core::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.bus.parse::<u32>() {
+            Ok(bus) => write!(f, "{bus}")?,
+            Err(_) => write!(f, "{}", self.bus)?,
+        }
+        for (index, port) in self.ports.iter().enumerate() {
+            write!(f, "{}{port}", if index == 0 { '-' } else { '.' })?;
+        }
+        Ok(())
+    }
+}
+
+/// Bus IDs are numbers on some platforms, which may or may not be written
+/// with leading zeros.
+fn same_bus(a: &str, b: &str) -> bool {
+    match (a.parse::<u32>(), b.parse::<u32>()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn parse_hex_id(s: &str, what: &str) -> Result<u16, String> {
+    u16::from_str_radix(s, 16).map_err(|e| format!("invalid {what} {s:?}: {e}"))
+}
+
+/// A vendor and product ID, with an optional index to tell several identical
+/// hubs apart, such as `0424:2514#1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VidPid {
+    vid: u16,
+    pid: u16,
+    nth: Option<usize>,
+}
+
+impl FromStr for VidPid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ids, nth) = match s.split_once('#') {
+            Some((ids, nth)) => (
+                ids,
+                Some(
+                    nth.parse()
+                        .map_err(|e| format!("invalid index in {s:?}: {e}"))?,
+                ),
+            ),
+            None => (s, None),
+        };
+        let (vid, pid) = ids
+            .split_once(':')
+            .ok_or_else(|| format!("expected VID:PID, got {s:?}"))?;
+        Ok(VidPid {
+            vid: parse_hex_id(vid, "vendor ID")?,
+            pid: parse_hex_id(pid, "product ID")?,
+            nth,
+        })
+    }
+}
+
+impl core::fmt::Display for VidPid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vid, self.pid)?;
+        if let Some(nth) = self.nth {
+            write!(f, "#{nth}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A device, given either by its IDs and optionally its serial number, such
+/// as `1366:0105:000123456`, by `serial=000123456`, or by part of its
+/// product string with `product=J-Link`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceMatch {
+    Ids {
+        vid: u16,
+        pid: u16,
+        serial: Option<String>,
+    },
+    Serial(String),
+    Product(String),
+}
+
+impl DeviceMatch {
+    pub fn matches(&self, device: &impl UsbDevice) -> bool {
+        match self {
+            DeviceMatch::Ids { vid, pid, serial } => {
+                device.vendor_id() == *vid
+                    && device.product_id() == *pid
+                    && serial
+                        .as_deref()
+                        .is_none_or(|serial| device.serial_number() == Some(serial))
+            }
+            DeviceMatch::Serial(serial) => device.serial_number() == Some(serial.as_str()),
+            DeviceMatch::Product(product) => device
+                .product_string()
+                .is_some_and(|name| name.contains(product.as_str())),
+        }
+    }
+}
+
+impl FromStr for DeviceMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(serial) = s.strip_prefix("serial=") {
+            return Ok(DeviceMatch::Serial(serial.to_owned()));
+        }
+        if let Some(product) = s.strip_prefix("product=") {
+            return Ok(DeviceMatch::Product(product.to_owned()));
+        }
+        let mut parts = s.splitn(3, ':');
+        let (Some(vid), Some(pid)) = (parts.next(), parts.next()) else {
+            return Err(format!(
+                "expected VID:PID[:SERIAL], serial=SERIAL or product=NAME, got {s:?}"
+            ));
+        };
+        Ok(DeviceMatch::Ids {
+            vid: parse_hex_id(vid, "vendor ID")?,
+            pid: parse_hex_id(pid, "product ID")?,
+            serial: parts.next().map(|serial| serial.to_owned()),
+        })
+    }
+}
+
+impl core::fmt::Display for DeviceMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceMatch::Ids { vid, pid, serial } => {
+                write!(f, "{vid:04x}:{pid:04x}")?;
+                if let Some(serial) = serial {
+                    write!(f, ":{serial}")?;
+                }
+                Ok(())
+            }
+            DeviceMatch::Serial(serial) => write!(f, "serial={serial}"),
+            DeviceMatch::Product(product) => write!(f, "product={product}"),
+        }
+    }
+}
+
+/// If `child` is plugged directly into `hub`, return the port it's on.
+pub fn hub_port_of(hub: &impl UsbDevice, child: &impl UsbDevice) -> Option<u8> {
+    if !same_bus(child.bus_id(), hub.bus_id()) {
+        return None;
+    }
+    let pc = hub.port_chain();
+    let cpc = child.port_chain();
+    if cpc.len() != pc.len() + 1 {
+        return None;
+    }
+    if cpc[0..pc.len()] != *pc {
+        return None;
+    }
+    Some(cpc[cpc.len() - 1])
+}
+
+#[derive(Debug)]
+pub enum LookupError {
+    NoSuchHub(HubSelector),
+    AmbiguousHub(HubSelector, Vec<Location>),
+    NoSuchPort(u8, usize),
+    NoSuchDevice(DeviceMatch),
+    AmbiguousDevice(DeviceMatch, Vec<u8>),
+}
+
+impl core::fmt::Display for LookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LookupError::NoSuchHub(selector) => write!(f, "no {selector}"),
+            LookupError::AmbiguousHub(selector, candidates) => {
+                let candidates: Vec<String> = candidates.iter().map(|c| c.to_string()).collect();
+                write!(
+                    f,
+                    "more than one {selector}, found hubs at {}",
+                    candidates.join(", ")
+                )
+            }
+            LookupError::NoSuchPort(port, count) => {
+                write!(f, "no port {port} (hub has {count} ports)")
+            }
+            LookupError::NoSuchDevice(device) => {
+                write!(f, "no device {device} attached to the hub")
+            }
+            LookupError::AmbiguousDevice(device, ports) => {
+                let ports: Vec<String> = ports.iter().map(|port| port.to_string()).collect();
+                write!(
+                    f,
+                    "device {device} is attached to ports {}",
+                    ports.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for LookupError {}
+
+/// The different ways of picking a hub on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HubSelector {
+    /// Position in the `hubctl list` output
+    Index(usize),
+    Location(Location),
+    Serial(String),
+    VidPid(VidPid),
+    /// The hub that this device is plugged into
+    Containing(DeviceMatch),
+}
+
+impl HubSelector {
+    /// Find the single hub in `hubs` that this refers to, returning its
+    /// index. `devices` is everything on the system, and is used to find
+    /// hubs by what's plugged into them.
+    pub fn select<D: UsbDevice>(&self, hubs: &[D], devices: &[D]) -> Result<usize, LookupError> {
+        let matches: Vec<usize> = match self {
+            HubSelector::Index(index) => {
+                return if *index < hubs.len() {
+                    Ok(*index)
+                } else {
+                    Err(LookupError::NoSuchHub(self.clone()))
+                };
+            }
+            HubSelector::Location(location) => (0..hubs.len())
+                .filter(|index| location.matches(&hubs[*index]))
+                .collect(),
+            HubSelector::Serial(serial) => (0..hubs.len())
+                .filter(|index| hubs[*index].serial_number() == Some(serial.as_str()))
+                .collect(),
+            HubSelector::VidPid(ids) => {
+                let matches: Vec<usize> = (0..hubs.len())
+                    .filter(|index| {
+                        hubs[*index].vendor_id() == ids.vid && hubs[*index].product_id() == ids.pid
+                    })
+                    .collect();
+                if let Some(nth) = ids.nth {
+                    return matches
+                        .get(nth)
+                        .copied()
+                        .ok_or_else(|| LookupError::NoSuchHub(self.clone()));
+                }
+                matches
+            }
+            HubSelector::Containing(device) => (0..hubs.len())
+                .filter(|index| {
+                    devices.iter().any(|child| {
+                        device.matches(child) && hub_port_of(&hubs[*index], child).is_some()
+                    })
+                })
+                .collect(),
+        };
+        match matches.as_slice() {
+            [] => Err(LookupError::NoSuchHub(self.clone())),
+            [index] => Ok(*index),
+            _ => Err(LookupError::AmbiguousHub(
+                self.clone(),
+                matches
+                    .iter()
+                    .map(|index| Location::of(&hubs[*index]))
+                    .collect(),
+            )),
+        }
+    }
+}
+
+impl core::fmt::Display for HubSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HubSelector::Index(index) => write!(f, "hub {index}"),
+            HubSelector::Location(location) => write!(f, "hub at {location}"),
+            HubSelector::Serial(serial) => write!(f, "hub with serial number {serial}"),
+            HubSelector::VidPid(ids) => write!(f, "hub {ids}"),
+            HubSelector::Containing(device) => write!(f, "hub with device {device} attached"),
+        }
+    }
+}
+
+/// The different ways of picking a port on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortSelector {
+    Number(u8),
+    /// The port that this device is plugged into
+    Device(DeviceMatch),
+}
+
+impl PortSelector {
+    /// Find the port of `hub` that this refers to, using `devices` to find
+    /// what's plugged into it.
+    pub fn select<D: UsbDevice>(&self, hub: &D, devices: &[D]) -> Result<u8, LookupError> {
+        let device = match self {
+            PortSelector::Number(port) => return Ok(*port),
+            PortSelector::Device(device) => device,
+        };
+        let mut ports: Vec<u8> = devices
+            .iter()
+            .filter(|child| device.matches(*child))
+            .filter_map(|child| hub_port_of(hub, child))
+            .collect();
+        ports.sort_unstable();
+        ports.dedup();
+        match ports.as_slice() {
+            [] => Err(LookupError::NoSuchDevice(device.clone())),
+            [port] => Ok(*port),
+            _ => Err(LookupError::AmbiguousDevice(device.clone(), ports)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::PowerSwitching;
+    use crate::sim::{SimDevice, SimHub};
+    use crate::topology::hub_infos;
+
+    fn jlink() -> SimDevice {
+        SimDevice::new(0x1366, 0x0105)
+            .with_product("J-Link")
+            .with_serial_number("000123456")
+    }
+
+    fn ftdi() -> SimDevice {
+        SimDevice::new(0x0403, 0x6001)
+    }
+
+    /// Two identical hubs, one with a J-Link and a cascaded hub attached,
+    /// and an FTDI cable on each of the others. The second hub's bus ID is
+    /// written with leading zeros, as some platforms do.
+    fn system() -> (Vec<SimDevice>, Vec<SimDevice>) {
+        let first = SimHub::test_hub(4);
+        let second = SimHub::new(
+            SimDevice::hub("001", &[3], 0x0424, 0x2514).with_serial_number("B"),
+            4,
+            PowerSwitching::Individual,
+        );
+        let cascaded = SimHub::new(
+            SimDevice::hub("1", &[2, 4], 0x05e3, 0x0610),
+            4,
+            PowerSwitching::Individual,
+        );
+        first.attach(1, jlink());
+        first.attach(4, cascaded.info().clone());
+        second.attach(2, ftdi());
+        cascaded.attach(3, ftdi());
+
+        let mut devices = first.devices();
+        devices.extend(second.devices());
+        devices.extend(cascaded.devices().into_iter().skip(1));
+        (hub_infos(&devices), devices)
+    }
+
+    /// Where the hub `selector` picks is, or why it couldn't pick one.
+    fn select(selector: HubSelector) -> Result<String, String> {
+        let (hubs, devices) = system();
+        selector
+            .select(&hubs, &devices)
+            .map(|index| Location::of(&hubs[index]).to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn locations_parse() {
+        for (text, bus, ports) in [
+            ("3-1.4", "3", &[1, 4][..]),
+            ("3-1", "3", &[1]),
+            ("usb1", "usb1", &[]),
+        ] {
+            let location: Location = text.parse().unwrap();
+            assert_eq!(location.bus, bus, "{text}");
+            assert_eq!(location.ports, ports, "{text}");
+        }
+        for text in ["-1", "1-", "1-x", "1-2..3", "1-256"] {
+            assert!(text.parse::<Location>().is_err(), "{text}");
+        }
+        assert_eq!("003-1.4".parse::<Location>().unwrap().to_string(), "3-1.4");
+        assert_eq!(
+            "3-1.4".parse::<Location>().unwrap().parent(),
+            Some(("3-1".parse().unwrap(), 4))
+        );
+        assert_eq!("3".parse::<Location>().unwrap().parent(), None);
+    }
+
+    #[test]
+    fn locations_match_bus_numbers() {
+        let hub = SimDevice::hub("001", &[3], 0x0424, 0x2514);
+        for (text, matches) in [
+            ("1-3", true),
+            ("001-3", true),
+            ("01-3", true),
+            ("2-3", false),
+            ("1-3.1", false),
+            ("1", false),
+        ] {
+            assert_eq!(
+                text.parse::<Location>().unwrap().matches(&hub),
+                matches,
+                "{text}"
+            );
+        }
+        let named = SimDevice::hub("usb1", &[], 0x1d6b, 0x0002);
+        assert!("usb1".parse::<Location>().unwrap().matches(&named));
+        assert!(!"usb2".parse::<Location>().unwrap().matches(&named));
+    }
+
+    #[test]
+    fn vid_pids_parse() {
+        for (text, vid, pid, nth) in [
+            ("0424:2514", 0x0424, 0x2514, None),
+            ("0424:2514#1", 0x0424, 0x2514, Some(1)),
+            ("424:abcd", 0x0424, 0xabcd, None),
+        ] {
+            assert_eq!(text.parse(), Ok(VidPid { vid, pid, nth }), "{text}");
+        }
+        for text in [
+            "0424",
+            "0424:",
+            "zzzz:2514",
+            "0424:12345",
+            "0424:2514#",
+            "0424:2514#x",
+        ] {
+            assert!(text.parse::<VidPid>().is_err(), "{text}");
+        }
+        assert_eq!(
+            "424:2514#2".parse::<VidPid>().unwrap().to_string(),
+            "0424:2514#2"
+        );
+    }
+
+    #[test]
+    fn device_matches_parse() {
+        for (text, expected) in [
+            (
+                "1366:0105",
+                DeviceMatch::Ids {
+                    vid: 0x1366,
+                    pid: 0x0105,
+                    serial: None,
+                },
+            ),
+            (
+                "1366:0105:000123456",
+                DeviceMatch::Ids {
+                    vid: 0x1366,
+                    pid: 0x0105,
+                    serial: Some("000123456".to_owned()),
+                },
+            ),
+            (
+                "serial=000123456",
+                DeviceMatch::Serial("000123456".to_owned()),
+            ),
+            ("product=J-Link", DeviceMatch::Product("J-Link".to_owned())),
+        ] {
+            assert_eq!(text.parse(), Ok(expected.clone()), "{text}");
+            assert_eq!(expected.to_string(), text);
+        }
+        for text in ["1366", "J-Link", "1366:xyz"] {
+            assert!(text.parse::<DeviceMatch>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn device_matches_match() {
+        for (text, matches) in [
+            ("1366:0105", true),
+            ("1366:0105:000123456", true),
+            ("1366:0105:999", false),
+            ("0403:6001", false),
+            ("serial=000123456", true),
+            ("serial=000123", false),
+            ("product=Link", true),
+            ("product=ST-Link", false),
+        ] {
+            let device: DeviceMatch = text.parse().unwrap();
+            assert_eq!(device.matches(&jlink()), matches, "{text}");
+        }
+        assert!(
+            !"product=J-Link"
+                .parse::<DeviceMatch>()
+                .unwrap()
+                .matches(&ftdi())
+        );
+    }
+
+    #[test]
+    fn ports_of_hubs() {
+        let hub = SimDevice::hub("001", &[3], 0x0424, 0x2514);
+        let on = |bus: &str, port_chain: &[u8]| SimDevice {
+            bus_id: bus.to_owned(),
+            port_chain: port_chain.to_vec(),
+            ..ftdi()
+        };
+        assert_eq!(hub_port_of(&hub, &on("001", &[3, 2])), Some(2));
+        // The same bus as `--location` sees it.
+        assert_eq!(hub_port_of(&hub, &on("1", &[3, 2])), Some(2));
+        assert_eq!(hub_port_of(&hub, &on("2", &[3, 2])), None);
+        assert_eq!(hub_port_of(&hub, &on("1", &[3, 2, 1])), None);
+        assert_eq!(hub_port_of(&hub, &on("1", &[4, 2])), None);
+        assert_eq!(hub_port_of(&hub, &on("1", &[3])), None);
+    }
+
+    #[test]
+    fn hubs_are_selected() {
+        let vid_pid = |text: &str| HubSelector::VidPid(text.parse().unwrap());
+        let containing = |text: &str| HubSelector::Containing(text.parse().unwrap());
+        for (selector, expected) in [
+            (HubSelector::Index(0), Ok("1-2")),
+            (HubSelector::Index(2), Ok("1-3")),
+            (HubSelector::Index(3), Err("no hub 3")),
+            (HubSelector::Location("1-2.4".parse().unwrap()), Ok("1-2.4")),
+            (HubSelector::Location("001-3".parse().unwrap()), Ok("1-3")),
+            (
+                HubSelector::Location("2-3".parse().unwrap()),
+                Err("no hub at 2-3"),
+            ),
+            (HubSelector::Serial("B".to_owned()), Ok("1-3")),
+            (
+                HubSelector::Serial("C".to_owned()),
+                Err("no hub with serial number C"),
+            ),
+            (vid_pid("05e3:0610"), Ok("1-2.4")),
+            (
+                vid_pid("0424:2514"),
+                Err("more than one hub 0424:2514, found hubs at 1-2, 1-3"),
+            ),
+            (vid_pid("0424:2514#0"), Ok("1-2")),
+            (vid_pid("0424:2514#1"), Ok("1-3")),
+            (vid_pid("0424:2514#2"), Err("no hub 0424:2514#2")),
+            (containing("product=J-Link"), Ok("1-2")),
+            (
+                containing("0403:6001"),
+                Err("more than one hub with device 0403:6001 attached, found hubs at 1-2.4, 1-3"),
+            ),
+            (
+                containing("serial=nothing"),
+                Err("no hub with device serial=nothing attached"),
+            ),
+        ] {
+            let description = selector.to_string();
+            assert_eq!(
+                select(selector),
+                expected.map(str::to_owned).map_err(str::to_owned),
+                "{description}"
+            );
+        }
+    }
+
+    #[test]
+    fn ports_are_selected_by_device() {
+        let (hubs, devices) = system();
+        let [first, cascaded, second] = [0, 1, 2].map(|index| &hubs[index]);
+        let port = |hub: &SimDevice, text: &str| {
+            PortSelector::Device(text.parse().unwrap())
+                .select(hub, &devices)
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(PortSelector::Number(3).select(first, &devices).unwrap(), 3);
+        assert_eq!(port(first, "product=J-Link"), Ok(1));
+        assert_eq!(port(first, "serial=000123456"), Ok(1));
+        // The cascaded hub is itself attached to a port.
+        assert_eq!(port(first, "05e3:0610"), Ok(4));
+        assert_eq!(port(second, "0403:6001"), Ok(2));
+        assert_eq!(port(cascaded, "0403:6001"), Ok(3));
+        // The FTDI cables are on other hubs, one of them below this one.
+        assert_eq!(
+            port(first, "0403:6001"),
+            Err("no device 0403:6001 attached to the hub".to_owned())
+        );
+        assert_eq!(
+            port(second, "product=J-Link"),
+            Err("no device product=J-Link attached to the hub".to_owned())
+        );
+    }
+
+    #[test]
+    fn ports_with_the_same_device_are_ambiguous() {
+        let hub = SimHub::test_hub(4);
+        hub.attach(1, SimDevice::new(0x0403, 0x6001));
+        hub.attach(3, SimDevice::new(0x0403, 0x6001));
+        let selector = PortSelector::Device("0403:6001".parse().unwrap());
+        assert_eq!(
+            selector
+                .select(hub.info(), &hub.devices())
+                .unwrap_err()
+                .to_string(),
+            "device 0403:6001 is attached to ports 1, 3"
+        );
+    }
+}
@@ -0,0 +1,732 @@
+//! A simulated hub, for exercising hubctl without any hardware attached.
+//!
+//! [`SimHub`] answers the class requests that [`HubControl`] sends, keeping
+//! track of the power and connection state of each port, the change bits,
+//! and which [`SimDevice`] is plugged into each port:
+//!
+//! ```
+//! # use hubctl::{HubControl, descriptor::PowerSwitching, sim::{SimDevice, SimHub}};
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), hubctl::Error> {
+//! let hub = SimHub::new(SimDevice::hub("1", &[2], 0x0424, 0x2514), 4, PowerSwitching::Individual);
+//! hub.attach(3, SimDevice::new(0x1366, 0x0105));
+//! let control = HubControl::with_transport(hub.clone(), false);
+//! control.off(3).await?;
+//! assert!(!control.status(3).await?.connected());
+//! assert_eq!(hub.devices().len(), 1);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`HubControl`]: crate::HubControl
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use nusb::transfer::{ControlIn, ControlOut, ControlType, Recipient, TransferError};
+use serde::{Deserialize, Serialize};
+
+use crate::bos::BOS_DESCRIPTOR_TYPE;
+use crate::control::{UsbDescriptorType, UsbRequest, feature};
+use crate::descriptor::PowerSwitching;
+use crate::device::UsbDevice;
+use crate::events::ChangeSource;
+use crate::indicator::Indicator;
+use crate::status::{LinkState, change, hub, port};
+use crate::transport::Transport;
+
+/// A simulated USB device. Recordings also keep the devices that were
+/// listed as these.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimDevice {
+    pub bus_id: String,
+    pub port_chain: Vec<u8>,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub class: u8,
+    pub usb_version: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+}
+
+impl SimDevice {
+    /// A USB 2.0 device that hasn't been plugged in anywhere yet.
+    pub fn new(vendor_id: u16, product_id: u16) -> Self {
+        SimDevice {
+            bus_id: String::new(),
+            port_chain: vec![],
+            vendor_id,
+            product_id,
+            class: 0,
+            usb_version: 0x0200,
+            manufacturer: None,
+            product: None,
+            serial_number: None,
+        }
+    }
+
+    /// A copy of what `device` says about itself.
+    pub fn of(device: &impl UsbDevice) -> Self {
+        SimDevice {
+            bus_id: device.bus_id().to_owned(),
+            port_chain: device.port_chain().to_vec(),
+            vendor_id: device.vendor_id(),
+            product_id: device.product_id(),
+            class: device.class(),
+            usb_version: device.usb_version(),
+            manufacturer: device.manufacturer_string().map(str::to_owned),
+            product: device.product_string().map(str::to_owned),
+            serial_number: device.serial_number().map(str::to_owned),
+        }
+    }
+
+    /// A USB 2.0 hub at the given location.
+    pub fn hub(bus_id: &str, port_chain: &[u8], vendor_id: u16, product_id: u16) -> Self {
+        SimDevice {
+            bus_id: bus_id.to_owned(),
+            port_chain: port_chain.to_vec(),
+            class: 0x09,
+            ..SimDevice::new(vendor_id, product_id)
+        }
+    }
+
+    pub fn with_usb_version(mut self, usb_version: u16) -> Self {
+        self.usb_version = usb_version;
+        self
+    }
+
+    pub fn with_product(mut self, product: &str) -> Self {
+        self.product = Some(product.to_owned());
+        self
+    }
+
+    pub fn with_serial_number(mut self, serial_number: &str) -> Self {
+        self.serial_number = Some(serial_number.to_owned());
+        self
+    }
+}
+
+impl UsbDevice for SimDevice {
+    fn bus_id(&self) -> &str {
+        &self.bus_id
+    }
+
+    fn port_chain(&self) -> &[u8] {
+        &self.port_chain
+    }
+
+    fn vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
+    fn product_id(&self) -> u16 {
+        self.product_id
+    }
+
+    fn class(&self) -> u8 {
+        self.class
+    }
+
+    fn usb_version(&self) -> u16 {
+        self.usb_version
+    }
+
+    fn manufacturer_string(&self) -> Option<&str> {
+        self.manufacturer.as_deref()
+    }
+
+    fn product_string(&self) -> Option<&str> {
+        self.product.as_deref()
+    }
+
+    fn serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
+}
+
+/// The power switching mode bits of `wHubCharacteristics`.
+fn switching_bits(switching: PowerSwitching) -> u8 {
+    match switching {
+        PowerSwitching::Ganged => 0b00,
+        PowerSwitching::Individual => 0b01,
+        PowerSwitching::NoSwitching => 0b10,
+    }
+}
+
+#[derive(Debug, Default)]
+struct SimPort {
+    status: u16,
+    change: u16,
+    /// Whatever is plugged in, whether or not the port is powered
+    device: Option<SimDevice>,
+    /// The port indicator selector
+    indicator: u8,
+}
+
+#[derive(Debug)]
+struct State {
+    hub_status: u16,
+    hub_change: u16,
+    ports: Vec<SimPort>,
+}
+
+/// A simulated hub. Clones share the same state, so one can be handed to
+/// [`HubControl::with_transport`](crate::HubControl::with_transport) while
+/// the test keeps another to plug devices in and look at the result.
+#[derive(Debug, Clone)]
+pub struct SimHub {
+    info: SimDevice,
+    descriptor: Vec<u8>,
+    bos: Option<Vec<u8>>,
+    switching: PowerSwitching,
+    state: Arc<Mutex<State>>,
+    changed: Arc<tokio::sync::Notify>,
+}
+
+impl SimHub {
+    /// A hub with `ports` ports, all powered and with nothing attached. It's
+    /// a SuperSpeed hub if `info` has a USB version of 3.0 or later.
+    pub fn new(info: SimDevice, ports: u8, switching: PowerSwitching) -> Self {
+        // per-port over-current protection
+        let characteristics = u16::from(switching_bits(switching)) | (0b01 << 3);
+        let [lo, hi] = characteristics.to_le_bytes();
+        let descriptor = if info.is_superspeed() {
+            vec![12, 0x2a, ports, lo, hi, 50, 25, 0, 0, 0, 0, 0]
+        } else {
+            let removable_len = (ports as usize + 1).div_ceil(8);
+            let mut descriptor = vec![0, 0x29, ports, lo, hi, 50, 100];
+            descriptor.extend(std::iter::repeat_n(0x00, removable_len));
+            descriptor.extend(std::iter::repeat_n(0xff, removable_len));
+            descriptor[0] = descriptor.len() as u8;
+            descriptor
+        };
+        let hub = SimHub {
+            info,
+            descriptor,
+            bos: None,
+            switching,
+            state: Arc::new(Mutex::new(State {
+                hub_status: 0,
+                hub_change: 0,
+                ports: (0..ports).map(|_| SimPort::default()).collect(),
+            })),
+            changed: Arc::new(tokio::sync::Notify::new()),
+        };
+        let mut state = hub.state.lock().unwrap();
+        for index in 0..ports as usize {
+            hub.power(&mut state, index, true);
+        }
+        state.ports.iter_mut().for_each(|port| port.change = 0);
+        drop(state);
+        hub
+    }
+
+    /// Switch power the way `switching` says, rather than how the hub was
+    /// created.
+    pub fn with_power_switching(mut self, switching: PowerSwitching) -> Self {
+        self.descriptor[3] = (self.descriptor[3] & !0b11) | switching_bits(switching);
+        self.switching = switching;
+        self
+    }
+
+    /// Advertise port indicators in the hub descriptor, which lets the host
+    /// set the colour of each port's LED. USB 2.0 hubs only.
+    pub fn with_port_indicators(mut self) -> Self {
+        if !self.info.is_superspeed() {
+            self.descriptor[3] |= 1 << 7;
+        }
+        self
+    }
+
+    /// Report `id` in a Container ID capability in the hub's BOS
+    /// descriptor. Hubs without one stall requests for the BOS descriptor.
+    pub fn with_container_id(mut self, id: [u8; 16]) -> Self {
+        let mut bos = vec![5, BOS_DESCRIPTOR_TYPE, 0, 0, 1];
+        bos.extend([20, 0x10, 0x04, 0]);
+        bos.extend(id);
+        let length = bos.len() as u16;
+        bos[2..4].copy_from_slice(&length.to_le_bytes());
+        self.bos = Some(bos);
+        self
+    }
+
+    /// The USB 2.0 hub most tests use: a 0424:2514 on port 2 of bus 1, with
+    /// `ports` ports switched one at a time.
+    #[cfg(test)]
+    pub(crate) fn test_hub(ports: u8) -> Self {
+        SimHub::new(
+            SimDevice::hub("1", &[2], 0x0424, 0x2514),
+            ports,
+            PowerSwitching::Individual,
+        )
+    }
+
+    /// Like [`SimHub::test_hub`], but a SuperSpeed 0424:5534.
+    #[cfg(test)]
+    pub(crate) fn test_superspeed_hub(ports: u8) -> Self {
+        SimHub::new(
+            SimDevice::hub("1", &[2], 0x0424, 0x5534).with_usb_version(0x0300),
+            ports,
+            PowerSwitching::Individual,
+        )
+    }
+
+    /// Describe the hub as one of `devices`, the way
+    /// [`Hub::describe`](crate::topology::Hub::describe) does for real hubs.
+    #[cfg(test)]
+    pub(crate) async fn describe(&self, devices: &[SimDevice]) -> crate::topology::Hub<SimDevice> {
+        self.describe_with_status(devices).await.0
+    }
+
+    /// Like [`SimHub::describe`], but also read the status of each port, the
+    /// way [`Tree::read`](crate::tree::Tree::read) does.
+    #[cfg(test)]
+    pub(crate) async fn describe_with_status(
+        &self,
+        devices: &[SimDevice],
+    ) -> (
+        crate::topology::Hub<SimDevice>,
+        Vec<Option<crate::status::PortStatus>>,
+    ) {
+        let control = crate::HubControl::with_transport(self.clone(), self.info.is_superspeed());
+        let index = crate::topology::hub_infos(devices)
+            .iter()
+            .position(|info| info == &self.info)
+            .expect("the hub should be one of the devices");
+        let hub =
+            crate::topology::Hub::new(index, &self.info, control.descriptor().await.ok(), devices)
+                .with_container_id(control.container_id().await.unwrap_or(None));
+        let mut statuses = vec![];
+        for port in 1..=hub.port_count() {
+            statuses.push(control.status(port).await.ok());
+        }
+        (hub, statuses)
+    }
+
+    pub fn info(&self) -> &SimDevice {
+        &self.info
+    }
+
+    /// Plug `device` into `port`, placing it below the hub in the topology.
+    pub fn attach(&self, port: u8, device: SimDevice) {
+        let mut state = self.state.lock().unwrap();
+        let mut port_chain = self.info.port_chain.clone();
+        port_chain.push(port);
+        let sim_port = &mut state.ports[port as usize - 1];
+        sim_port.device = Some(SimDevice {
+            bus_id: self.info.bus_id.clone(),
+            port_chain,
+            ..device
+        });
+        if sim_port.status & self.power_bit() != 0 {
+            self.connect(sim_port);
+        }
+    }
+
+    /// Unplug whatever is attached to `port`.
+    pub fn detach(&self, port: u8) {
+        let mut state = self.state.lock().unwrap();
+        let sim_port = &mut state.ports[port as usize - 1];
+        sim_port.device = None;
+        if sim_port.status & port::CONNECTION != 0 {
+            self.disconnect(sim_port);
+        }
+    }
+
+    /// Start or stop an over-current condition on `port`. Like a real hub,
+    /// the port loses power, and trips again if it's powered while the
+    /// condition lasts.
+    pub fn set_over_current(&self, port: u8, active: bool) {
+        let mut state = self.state.lock().unwrap();
+        let index = port as usize - 1;
+        if active {
+            self.power(&mut state, index, false);
+            state.ports[index].status |= port::OVER_CURRENT;
+        } else {
+            state.ports[index].status &= !port::OVER_CURRENT;
+        }
+        state.ports[index].change |= change::OVER_CURRENT;
+        self.changed.notify_one();
+    }
+
+    /// Start or stop an over-current condition across the whole hub, as
+    /// reported by hubs with global over-current protection.
+    pub fn set_hub_over_current(&self, active: bool) {
+        let mut state = self.state.lock().unwrap();
+        if active {
+            state.hub_status |= hub::OVER_CURRENT;
+        } else {
+            state.hub_status &= !hub::OVER_CURRENT;
+        }
+        state.hub_change |= hub::OVER_CURRENT;
+        self.changed.notify_one();
+    }
+
+    /// What the indicator LED of `port` is showing.
+    pub fn indicator(&self, port: u8) -> Indicator {
+        let selector = self.state.lock().unwrap().ports[port as usize - 1].indicator;
+        Indicator::from_selector(selector).expect("only valid selectors are stored")
+    }
+
+    /// Lose or regain the hub's external power supply.
+    pub fn set_local_power_lost(&self, lost: bool) {
+        let mut state = self.state.lock().unwrap();
+        if lost {
+            state.hub_status |= hub::LOCAL_POWER;
+        } else {
+            state.hub_status &= !hub::LOCAL_POWER;
+        }
+        state.hub_change |= hub::LOCAL_POWER;
+        self.changed.notify_one();
+    }
+
+    pub fn is_powered(&self, port: u8) -> bool {
+        self.state.lock().unwrap().ports[port as usize - 1].status & self.power_bit() != 0
+    }
+
+    /// The devices the host can see: the hub itself, and whatever is
+    /// connected to one of its powered ports.
+    pub fn devices(&self) -> Vec<SimDevice> {
+        let state = self.state.lock().unwrap();
+        std::iter::once(self.info.clone())
+            .chain(
+                state
+                    .ports
+                    .iter()
+                    .filter(|port| port.status & port::CONNECTION != 0)
+                    .filter_map(|port| port.device.clone()),
+            )
+            .collect()
+    }
+
+    /// The bitmap the hub's status change endpoint would return: bit 0 if
+    /// the hub's own change bits are set, and a bit for each port with a
+    /// change bit set.
+    fn change_bitmap(&self) -> Vec<u8> {
+        let state = self.state.lock().unwrap();
+        let mut bitmap = vec![0; (state.ports.len() + 1).div_ceil(8)];
+        if state.hub_change != 0 {
+            bitmap[0] |= 1;
+        }
+        for (index, sim_port) in state.ports.iter().enumerate() {
+            if sim_port.change != 0 {
+                bitmap[(index + 1) / 8] |= 1 << ((index + 1) % 8);
+            }
+        }
+        bitmap
+    }
+
+    fn power_bit(&self) -> u16 {
+        if self.info.is_superspeed() {
+            port::SS_POWER
+        } else {
+            port::POWER
+        }
+    }
+
+    fn set_link_state(&self, sim_port: &mut SimPort, state: u16) {
+        if self.info.is_superspeed() {
+            sim_port.status =
+                (sim_port.status & !port::LINK_STATE_MASK) | (state << port::LINK_STATE_SHIFT);
+        }
+    }
+
+    fn connect(&self, sim_port: &mut SimPort) {
+        sim_port.status |= port::CONNECTION | port::ENABLE;
+        if !self.info.is_superspeed() {
+            sim_port.status |= port::HIGH_SPEED;
+        }
+        self.set_link_state(sim_port, 0x0 /* U0 */);
+        sim_port.change |= change::CONNECTION;
+        self.changed.notify_one();
+    }
+
+    fn disconnect(&self, sim_port: &mut SimPort) {
+        sim_port.status &= !(port::CONNECTION | port::ENABLE | port::HIGH_SPEED);
+        self.set_link_state(sim_port, 0x5 /* Rx.Detect */);
+        sim_port.change |= change::CONNECTION;
+        self.changed.notify_one();
+    }
+
+    /// Reset a port, finishing at once. An unpowered port ignores the
+    /// request, so the reset never completes.
+    fn reset(&self, sim_port: &mut SimPort, warm: bool) {
+        if sim_port.status & self.power_bit() == 0 {
+            return;
+        }
+        if sim_port.status & port::CONNECTION != 0 {
+            sim_port.status |= port::ENABLE;
+            self.set_link_state(sim_port, 0x0 /* U0 */);
+        }
+        sim_port.change |= if warm {
+            change::RESET | change::BH_RESET
+        } else {
+            change::RESET
+        };
+        self.changed.notify_one();
+    }
+
+    /// Suspend or resume a USB 2 port. Only enabled ports can be suspended,
+    /// and resuming finishes at once.
+    fn suspend(&self, sim_port: &mut SimPort, suspend: bool) {
+        let suspended = sim_port.status & port::SUSPEND != 0;
+        if suspend && !suspended && sim_port.status & port::ENABLE != 0 {
+            sim_port.status |= port::SUSPEND;
+        } else if !suspend && suspended {
+            sim_port.status &= !port::SUSPEND;
+            sim_port.change |= change::SUSPEND;
+            self.changed.notify_one();
+        }
+    }
+
+    /// Move the link of a SuperSpeed port. Disabling the port disconnects
+    /// the device until the port is sent to Rx.Detect, and requests for
+    /// states a host can't ask for stall.
+    fn move_link(&self, sim_port: &mut SimPort, target: LinkState) -> Result<(), TransferError> {
+        let current = LinkState::from(
+            ((sim_port.status & port::LINK_STATE_MASK) >> port::LINK_STATE_SHIFT) as u8,
+        );
+        if sim_port.status & self.power_bit() == 0 {
+            return Ok(());
+        }
+        match (current, target) {
+            (LinkState::U3, LinkState::U0) => {
+                self.set_link_state(sim_port, 0x0);
+                sim_port.change |= change::LINK_STATE;
+                self.changed.notify_one();
+            }
+            (
+                LinkState::U0 | LinkState::U1 | LinkState::U2,
+                LinkState::U0 | LinkState::U1 | LinkState::U2 | LinkState::U3,
+            ) => {
+                self.set_link_state(sim_port, u8::from(target).into());
+            }
+            (LinkState::Disabled, LinkState::RxDetect) => {
+                self.set_link_state(sim_port, 0x5 /* Rx.Detect */);
+                if sim_port.device.is_some() {
+                    self.connect(sim_port);
+                }
+            }
+            (_, LinkState::Disabled) => {
+                if sim_port.status & port::CONNECTION != 0 {
+                    self.disconnect(sim_port);
+                }
+                sim_port.status &= !port::ENABLE;
+                self.set_link_state(sim_port, 0x4 /* SS.Disabled */);
+            }
+            (
+                _,
+                LinkState::U0 | LinkState::U1 | LinkState::U2 | LinkState::U3 | LinkState::RxDetect,
+            ) => {}
+            _ => return Err(TransferError::Stall),
+        }
+        Ok(())
+    }
+
+    fn power(&self, state: &mut State, index: usize, on: bool) {
+        let sim_port = &mut state.ports[index];
+        if (sim_port.status & self.power_bit() != 0) == on {
+            return;
+        }
+        if on && sim_port.status & port::OVER_CURRENT != 0 {
+            // The port trips again as soon as it's powered.
+            sim_port.change |= change::OVER_CURRENT;
+            self.changed.notify_one();
+            return;
+        }
+        if on {
+            sim_port.status |= self.power_bit();
+            self.set_link_state(sim_port, 0x5 /* Rx.Detect */);
+            if sim_port.device.is_some() {
+                self.connect(sim_port);
+            }
+        } else {
+            if sim_port.status & port::CONNECTION != 0 {
+                self.disconnect(sim_port);
+            }
+            sim_port.status &= !self.power_bit();
+            self.set_link_state(sim_port, 0x4 /* SS.Disabled */);
+        }
+    }
+
+    fn port_index(state: &State, port: u16) -> Result<usize, TransferError> {
+        if port == 0 || port as usize > state.ports.len() {
+            return Err(TransferError::Stall);
+        }
+        Ok(port as usize - 1)
+    }
+
+    fn handle_in(&self, data: ControlIn) -> Result<Vec<u8>, TransferError> {
+        if data.control_type == ControlType::Standard
+            && data.recipient == Recipient::Device
+            && data.request == UsbRequest::GetDescriptor as u8
+            && data.value == u16::from(BOS_DESCRIPTOR_TYPE) << 8
+        {
+            let mut bos = self.bos.clone().ok_or(TransferError::Stall)?;
+            bos.truncate(data.length as usize);
+            return Ok(bos);
+        }
+        if data.control_type != ControlType::Class {
+            return Err(TransferError::Stall);
+        }
+        let state = self.state.lock().unwrap();
+        let mut response = match (data.recipient, data.request) {
+            (Recipient::Device, r) if r == UsbRequest::GetDescriptor as u8 => {
+                let expected = if self.info.is_superspeed() {
+                    UsbDescriptorType::SuperSpeedHub
+                } else {
+                    UsbDescriptorType::Hub
+                };
+                if data.value >> 8 != expected as u16 {
+                    return Err(TransferError::Stall);
+                }
+                self.descriptor.clone()
+            }
+            (Recipient::Device, r) if r == UsbRequest::GetStatus as u8 => [
+                state.hub_status.to_le_bytes(),
+                state.hub_change.to_le_bytes(),
+            ]
+            .concat(),
+            (Recipient::Other, r) if r == UsbRequest::GetStatus as u8 => {
+                let sim_port = &state.ports[Self::port_index(&state, data.index)?];
+                [sim_port.status.to_le_bytes(), sim_port.change.to_le_bytes()].concat()
+            }
+            _ => return Err(TransferError::Stall),
+        };
+        response.truncate(data.length as usize);
+        Ok(response)
+    }
+
+    fn handle_out(&self, data: ControlOut<'_>) -> Result<(), TransferError> {
+        if data.control_type == ControlType::Class && data.recipient == Recipient::Device {
+            return self.handle_hub_out(data);
+        }
+        if data.control_type != ControlType::Class || data.recipient != Recipient::Other {
+            return Err(TransferError::Stall);
+        }
+        let mut state = self.state.lock().unwrap();
+        // Some selectors take an argument in the upper byte of wIndex.
+        let index = Self::port_index(&state, data.index & 0xff)?;
+        let set = if data.request == UsbRequest::SetFeature as u8 {
+            true
+        } else if data.request == UsbRequest::ClearFeature as u8 {
+            false
+        } else {
+            return Err(TransferError::Stall);
+        };
+        let change_bit = match data.value {
+            feature::PORT_POWER => {
+                match self.switching {
+                    PowerSwitching::Individual => self.power(&mut state, index, set),
+                    PowerSwitching::Ganged => {
+                        for index in 0..state.ports.len() {
+                            self.power(&mut state, index, set);
+                        }
+                    }
+                    // Accept the request, but leave the port powered.
+                    PowerSwitching::NoSwitching => {}
+                }
+                return Ok(());
+            }
+            feature::PORT_RESET | feature::BH_PORT_RESET if set => {
+                self.reset(
+                    &mut state.ports[index],
+                    data.value == feature::BH_PORT_RESET,
+                );
+                return Ok(());
+            }
+            feature::PORT_INDICATOR if set && self.descriptor[3] & (1 << 7) != 0 => {
+                let selector = (data.index >> 8) as u8;
+                Indicator::from_selector(selector).ok_or(TransferError::Stall)?;
+                let sim_port = &mut state.ports[index];
+                sim_port.indicator = selector;
+                if selector == 0 {
+                    sim_port.status &= !port::INDICATOR;
+                } else {
+                    sim_port.status |= port::INDICATOR;
+                }
+                return Ok(());
+            }
+            feature::PORT_SUSPEND if !self.info.is_superspeed() => {
+                self.suspend(&mut state.ports[index], set);
+                return Ok(());
+            }
+            feature::PORT_LINK_STATE if set && self.info.is_superspeed() => {
+                let target = LinkState::from((data.index >> 8) as u8);
+                self.move_link(&mut state.ports[index], target)?;
+                return Ok(());
+            }
+            feature::C_PORT_CONNECTION => change::CONNECTION,
+            feature::C_PORT_ENABLE => change::ENABLE,
+            feature::C_PORT_SUSPEND => change::SUSPEND,
+            feature::C_PORT_OVER_CURRENT => change::OVER_CURRENT,
+            feature::C_PORT_RESET => change::RESET,
+            feature::C_BH_PORT_RESET => change::BH_RESET,
+            feature::C_PORT_LINK_STATE => change::LINK_STATE,
+            feature::C_PORT_CONFIG_ERROR => change::CONFIG_ERROR,
+            _ => return Err(TransferError::Stall),
+        };
+        if set {
+            return Err(TransferError::Stall);
+        }
+        state.ports[index].change &= !change_bit;
+        Ok(())
+    }
+
+    /// Handle requests to the hub itself, which only has change bits to
+    /// clear.
+    fn handle_hub_out(&self, data: ControlOut<'_>) -> Result<(), TransferError> {
+        if data.request != UsbRequest::ClearFeature as u8 {
+            return Err(TransferError::Stall);
+        }
+        let change_bit = match data.value {
+            feature::C_HUB_LOCAL_POWER => hub::LOCAL_POWER,
+            feature::C_HUB_OVER_CURRENT => hub::OVER_CURRENT,
+            _ => return Err(TransferError::Stall),
+        };
+        self.state.lock().unwrap().hub_change &= !change_bit;
+        Ok(())
+    }
+}
+
+impl Transport for SimHub {
+    fn control_in(
+        &self,
+        data: ControlIn,
+        _timeout: Duration,
+    ) -> impl Future<Output = Result<Vec<u8>, TransferError>> + Send {
+        std::future::ready(self.handle_in(data))
+    }
+
+    fn control_out(
+        &self,
+        data: ControlOut<'_>,
+        _timeout: Duration,
+    ) -> impl Future<Output = Result<(), TransferError>> + Send {
+        std::future::ready(self.handle_out(data))
+    }
+}
+
+/// The simulated status change endpoint, which completes once a port has a
+/// change bit set.
+impl ChangeSource for SimHub {
+    async fn next_change(&mut self) -> Result<Vec<u8>, TransferError> {
+        loop {
+            let changed = self.changed.notified();
+            let bitmap = self.change_bitmap();
+            if bitmap.iter().any(|byte| *byte != 0) {
+                return Ok(bitmap);
+            }
+            changed.await;
+        }
+    }
+
+    fn clears_changes(&self) -> bool {
+        true
+    }
+}
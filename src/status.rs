@@ -0,0 +1,741 @@
+//! Decoding of the port status returned by a hub's GetPortStatus request.
+//!
+//! USB 2.0 hubs use the layout from §11.24.2.7 of the USB 2.0 specification,
+//! while SuperSpeed hubs reuse the same four bytes with a different meaning
+//! (USB 3.2 §10.16.2.6), so the hub type has to be known to decode them.
+
+/// Bits in `wPortStatus` shared by both layouts.
+pub(crate) mod port {
+    pub const CONNECTION: u16 = 1 << 0;
+    pub const ENABLE: u16 = 1 << 1;
+    pub const OVER_CURRENT: u16 = 1 << 3;
+    pub const RESET: u16 = 1 << 4;
+
+    /// USB 2.0 only
+    pub const SUSPEND: u16 = 1 << 2;
+    pub const POWER: u16 = 1 << 8;
+    pub const LOW_SPEED: u16 = 1 << 9;
+    pub const HIGH_SPEED: u16 = 1 << 10;
+    pub const TEST: u16 = 1 << 11;
+    pub const INDICATOR: u16 = 1 << 12;
+
+    /// SuperSpeed only
+    pub const LINK_STATE_SHIFT: u16 = 5;
+    pub const LINK_STATE_MASK: u16 = 0xf << LINK_STATE_SHIFT;
+    pub const SS_POWER: u16 = 1 << 9;
+    pub const SPEED_SHIFT: u16 = 10;
+    pub const SPEED_MASK: u16 = 0x7 << SPEED_SHIFT;
+}
+
+/// Bits in `wPortChange`.
+pub(crate) mod change {
+    pub const CONNECTION: u16 = 1 << 0;
+    pub const ENABLE: u16 = 1 << 1;
+    pub const SUSPEND: u16 = 1 << 2;
+    pub const OVER_CURRENT: u16 = 1 << 3;
+    pub const RESET: u16 = 1 << 4;
+
+    /// SuperSpeed only
+    pub const BH_RESET: u16 = 1 << 5;
+    pub const LINK_STATE: u16 = 1 << 6;
+    pub const CONFIG_ERROR: u16 = 1 << 7;
+}
+
+/// Bits in `wHubStatus` and `wHubChange`, which share the same layout.
+pub(crate) mod hub {
+    pub const LOCAL_POWER: u16 = 1 << 0;
+    pub const OVER_CURRENT: u16 = 1 << 1;
+}
+
+/// The decoded `wHubStatus` and `wHubChange` fields, returned by GetHubStatus
+/// (USB 2.0 §11.24.2.6). The layout is the same for SuperSpeed hubs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HubStatus {
+    status: u16,
+    change: u16,
+}
+
+impl HubStatus {
+    /// Decode the four bytes returned by GetHubStatus. Returns `None` if the
+    /// hub sent back too little data.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 4 {
+            return None;
+        }
+        Some(HubStatus {
+            status: u16::from_le_bytes([data[0], data[1]]),
+            change: u16::from_le_bytes([data[2], data[3]]),
+        })
+    }
+
+    /// Whether the hub's external power supply has been lost, which leaves
+    /// a hub that can also run from the bus drawing bus power. Always false
+    /// for bus-powered hubs.
+    pub fn local_power_lost(&self) -> bool {
+        self.status & hub::LOCAL_POWER != 0
+    }
+
+    /// Whether the hub as a whole is reporting over-current, which hubs
+    /// with global over-current protection do instead of per-port reports.
+    pub fn over_current(&self) -> bool {
+        self.status & hub::OVER_CURRENT != 0
+    }
+
+    pub fn local_power_changed(&self) -> bool {
+        self.change & hub::LOCAL_POWER != 0
+    }
+
+    pub fn over_current_changed(&self) -> bool {
+        self.change & hub::OVER_CURRENT != 0
+    }
+
+    /// The status with every change bit cleared, as it reads once they've
+    /// been acknowledged.
+    pub(crate) fn acknowledged(self) -> Self {
+        HubStatus { change: 0, ..self }
+    }
+
+    pub fn has_changed(&self, change: HubChange) -> bool {
+        self.change & change.bit() != 0
+    }
+
+    /// Every change bit that is set.
+    pub fn changed(&self) -> Vec<HubChange> {
+        HubChange::ALL
+            .into_iter()
+            .filter(|change| self.has_changed(*change))
+            .collect()
+    }
+
+    /// Names of every change bit that is set.
+    pub fn changes(&self) -> Vec<&'static str> {
+        self.changed().into_iter().map(HubChange::name).collect()
+    }
+}
+
+impl core::fmt::Display for HubStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.local_power_lost() {
+            write!(f, "local power LOST")?;
+        } else {
+            write!(f, "local power good")?;
+        }
+        if self.over_current() {
+            write!(f, ", OVER-CURRENT")?;
+        }
+        let changed: Vec<String> = self.changed().iter().map(HubChange::to_string).collect();
+        if !changed.is_empty() {
+            write!(
+                f,
+                " (changed since last acknowledged: {})",
+                changed.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A bit in `wHubChange`, which like [`Change`] stays set until the host
+/// acknowledges it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HubChange {
+    LocalPower,
+    OverCurrent,
+}
+
+impl HubChange {
+    pub const ALL: [HubChange; 2] = [HubChange::LocalPower, HubChange::OverCurrent];
+
+    /// The name the specifications use, such as `C_HUB_LOCAL_POWER`.
+    pub fn name(self) -> &'static str {
+        match self {
+            HubChange::LocalPower => "C_HUB_LOCAL_POWER",
+            HubChange::OverCurrent => "C_HUB_OVER_CURRENT",
+        }
+    }
+
+    fn bit(self) -> u16 {
+        match self {
+            HubChange::LocalPower => hub::LOCAL_POWER,
+            HubChange::OverCurrent => hub::OVER_CURRENT,
+        }
+    }
+}
+
+impl core::fmt::Display for HubChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HubChange::LocalPower => write!(f, "local-power"),
+            HubChange::OverCurrent => write!(f, "over-current"),
+        }
+    }
+}
+
+/// Parses the short name or the specification's name, like [`Change`].
+impl core::str::FromStr for HubChange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        HubChange::ALL
+            .into_iter()
+            .find(|change| {
+                s.eq_ignore_ascii_case(&change.to_string()) || s.eq_ignore_ascii_case(change.name())
+            })
+            .ok_or_else(|| {
+                format!(
+                    "unknown hub change bit {s:?}, expected one of {}",
+                    HubChange::ALL.map(|change| change.to_string()).join(", ")
+                )
+            })
+    }
+}
+
+/// The speed of the device attached to a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortSpeed {
+    Low,
+    Full,
+    High,
+    /// A SuperSpeed port, along with the raw speed ID from `wPortStatus`.
+    SuperSpeed(u8),
+}
+
+impl core::fmt::Display for PortSpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortSpeed::Low => write!(f, "low-speed"),
+            PortSpeed::Full => write!(f, "full-speed"),
+            PortSpeed::High => write!(f, "high-speed"),
+            PortSpeed::SuperSpeed(0) => write!(f, "SuperSpeed"),
+            PortSpeed::SuperSpeed(id) => write!(f, "SuperSpeed (speed ID {id})"),
+        }
+    }
+}
+
+/// The link state of a SuperSpeed port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    U0,
+    U1,
+    U2,
+    U3,
+    Disabled,
+    RxDetect,
+    Inactive,
+    Polling,
+    Recovery,
+    HotReset,
+    Compliance,
+    Loopback,
+    Reserved(u8),
+}
+
+impl From<u8> for LinkState {
+    fn from(value: u8) -> Self {
+        match value {
+            0x0 => LinkState::U0,
+            0x1 => LinkState::U1,
+            0x2 => LinkState::U2,
+            0x3 => LinkState::U3,
+            0x4 => LinkState::Disabled,
+            0x5 => LinkState::RxDetect,
+            0x6 => LinkState::Inactive,
+            0x7 => LinkState::Polling,
+            0x8 => LinkState::Recovery,
+            0x9 => LinkState::HotReset,
+            0xa => LinkState::Compliance,
+            0xb => LinkState::Loopback,
+            other => LinkState::Reserved(other),
+        }
+    }
+}
+
+impl From<LinkState> for u8 {
+    fn from(state: LinkState) -> Self {
+        match state {
+            LinkState::U0 => 0x0,
+            LinkState::U1 => 0x1,
+            LinkState::U2 => 0x2,
+            LinkState::U3 => 0x3,
+            LinkState::Disabled => 0x4,
+            LinkState::RxDetect => 0x5,
+            LinkState::Inactive => 0x6,
+            LinkState::Polling => 0x7,
+            LinkState::Recovery => 0x8,
+            LinkState::HotReset => 0x9,
+            LinkState::Compliance => 0xa,
+            LinkState::Loopback => 0xb,
+            LinkState::Reserved(value) => value,
+        }
+    }
+}
+
+/// Parses the names [`LinkState`] displays as, ignoring case and
+/// punctuation, so `SS.Disabled`, `disabled` and `rx-detect` all work.
+impl core::str::FromStr for LinkState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalize = |name: &str| {
+            let name: String = name
+                .chars()
+                .filter(char::is_ascii_alphanumeric)
+                .map(|c| c.to_ascii_lowercase())
+                .collect();
+            name.strip_prefix("ss").map(str::to_owned).unwrap_or(name)
+        };
+        let wanted = normalize(s);
+        (0..=0xb)
+            .map(LinkState::from)
+            .find(|state| normalize(&state.to_string()) == wanted)
+            .ok_or_else(|| format!("unknown link state {s:?}"))
+    }
+}
+
+impl core::fmt::Display for LinkState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkState::U0 => write!(f, "U0"),
+            LinkState::U1 => write!(f, "U1"),
+            LinkState::U2 => write!(f, "U2"),
+            LinkState::U3 => write!(f, "U3"),
+            LinkState::Disabled => write!(f, "SS.Disabled"),
+            LinkState::RxDetect => write!(f, "Rx.Detect"),
+            LinkState::Inactive => write!(f, "SS.Inactive"),
+            LinkState::Polling => write!(f, "Polling"),
+            LinkState::Recovery => write!(f, "Recovery"),
+            LinkState::HotReset => write!(f, "Hot Reset"),
+            LinkState::Compliance => write!(f, "Compliance"),
+            LinkState::Loopback => write!(f, "Loopback"),
+            LinkState::Reserved(value) => write!(f, "reserved ({value:#x})"),
+        }
+    }
+}
+
+/// A bit in `wPortChange`, which the hub sets when something about the port
+/// changes and keeps set until the host acknowledges it with ClearFeature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Connection,
+    /// USB 2.0 only
+    Enable,
+    /// USB 2.0 only
+    Suspend,
+    OverCurrent,
+    Reset,
+    /// SuperSpeed only
+    BhReset,
+    /// SuperSpeed only
+    LinkState,
+    /// SuperSpeed only
+    ConfigError,
+}
+
+impl Change {
+    pub const ALL: [Change; 8] = [
+        Change::Connection,
+        Change::Enable,
+        Change::Suspend,
+        Change::OverCurrent,
+        Change::Reset,
+        Change::BhReset,
+        Change::LinkState,
+        Change::ConfigError,
+    ];
+
+    /// The name the specifications use, such as `C_PORT_CONNECTION`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Change::Connection => "C_PORT_CONNECTION",
+            Change::Enable => "C_PORT_ENABLE",
+            Change::Suspend => "C_PORT_SUSPEND",
+            Change::OverCurrent => "C_PORT_OVER_CURRENT",
+            Change::Reset => "C_PORT_RESET",
+            Change::BhReset => "C_BH_PORT_RESET",
+            Change::LinkState => "C_PORT_LINK_STATE",
+            Change::ConfigError => "C_PORT_CONFIG_ERROR",
+        }
+    }
+
+    /// Whether hubs of this kind have the bit at all.
+    pub fn applies_to(self, superspeed: bool) -> bool {
+        match self {
+            Change::Enable | Change::Suspend => !superspeed,
+            Change::BhReset | Change::LinkState | Change::ConfigError => superspeed,
+            Change::Connection | Change::OverCurrent | Change::Reset => true,
+        }
+    }
+
+    fn bit(self) -> u16 {
+        match self {
+            Change::Connection => change::CONNECTION,
+            Change::Enable => change::ENABLE,
+            Change::Suspend => change::SUSPEND,
+            Change::OverCurrent => change::OVER_CURRENT,
+            Change::Reset => change::RESET,
+            Change::BhReset => change::BH_RESET,
+            Change::LinkState => change::LINK_STATE,
+            Change::ConfigError => change::CONFIG_ERROR,
+        }
+    }
+}
+
+impl core::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Connection => write!(f, "connection"),
+            Change::Enable => write!(f, "enable"),
+            Change::Suspend => write!(f, "suspend"),
+            Change::OverCurrent => write!(f, "over-current"),
+            Change::Reset => write!(f, "reset"),
+            Change::BhReset => write!(f, "bh-reset"),
+            Change::LinkState => write!(f, "link-state"),
+            Change::ConfigError => write!(f, "config-error"),
+        }
+    }
+}
+
+/// Parses either the short name [`Change`] displays as, such as
+/// `over-current`, or the specification's name, such as
+/// `C_PORT_OVER_CURRENT`, ignoring case.
+impl core::str::FromStr for Change {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Change::ALL
+            .into_iter()
+            .find(|change| {
+                s.eq_ignore_ascii_case(&change.to_string()) || s.eq_ignore_ascii_case(change.name())
+            })
+            .ok_or_else(|| {
+                format!(
+                    "unknown change bit {s:?}, expected one of {}",
+                    Change::ALL.map(|change| change.to_string()).join(", ")
+                )
+            })
+    }
+}
+
+/// The decoded `wPortStatus` and `wPortChange` fields of a hub port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortStatus {
+    status: u16,
+    change: u16,
+    superspeed: bool,
+}
+
+impl PortStatus {
+    /// Decode the four bytes returned by GetPortStatus. Returns `None` if
+    /// the hub sent back too little data.
+    pub fn from_bytes(data: &[u8], superspeed: bool) -> Option<Self> {
+        if data.len() < 4 {
+            return None;
+        }
+        Some(PortStatus {
+            status: u16::from_le_bytes([data[0], data[1]]),
+            change: u16::from_le_bytes([data[2], data[3]]),
+            superspeed,
+        })
+    }
+
+    /// The same status with every change bit cleared, as it reads once the
+    /// changes have been acknowledged.
+    pub(crate) fn acknowledged(self) -> Self {
+        PortStatus { change: 0, ..self }
+    }
+
+    pub fn connected(&self) -> bool {
+        self.status & port::CONNECTION != 0
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.status & port::ENABLE != 0
+    }
+
+    /// USB 2.0 ports report suspend directly, SuperSpeed ports are
+    /// suspended when their link is in U3.
+    pub fn suspended(&self) -> bool {
+        if self.superspeed {
+            self.link_state() == Some(LinkState::U3)
+        } else {
+            self.status & port::SUSPEND != 0
+        }
+    }
+
+    pub fn over_current(&self) -> bool {
+        self.status & port::OVER_CURRENT != 0
+    }
+
+    pub fn resetting(&self) -> bool {
+        self.status & port::RESET != 0
+    }
+
+    pub fn powered(&self) -> bool {
+        if self.superspeed {
+            self.status & port::SS_POWER != 0
+        } else {
+            self.status & port::POWER != 0
+        }
+    }
+
+    /// The speed of the attached device, if there is one.
+    pub fn speed(&self) -> Option<PortSpeed> {
+        if !self.connected() {
+            return None;
+        }
+        Some(if self.superspeed {
+            PortSpeed::SuperSpeed(((self.status & port::SPEED_MASK) >> port::SPEED_SHIFT) as u8)
+        } else if self.status & port::LOW_SPEED != 0 {
+            PortSpeed::Low
+        } else if self.status & port::HIGH_SPEED != 0 {
+            PortSpeed::High
+        } else {
+            PortSpeed::Full
+        })
+    }
+
+    /// Whether the port is in test mode. Always `false` for SuperSpeed ports.
+    pub fn test_mode(&self) -> bool {
+        !self.superspeed && self.status & port::TEST != 0
+    }
+
+    /// Whether the port indicator is under software control. Always `false`
+    /// for SuperSpeed ports.
+    pub fn indicator_control(&self) -> bool {
+        !self.superspeed && self.status & port::INDICATOR != 0
+    }
+
+    /// The link state of a SuperSpeed port. `None` for USB 2.0 ports.
+    pub fn link_state(&self) -> Option<LinkState> {
+        if !self.superspeed {
+            return None;
+        }
+        Some(LinkState::from(
+            ((self.status & port::LINK_STATE_MASK) >> port::LINK_STATE_SHIFT) as u8,
+        ))
+    }
+
+    pub fn connection_changed(&self) -> bool {
+        self.change & change::CONNECTION != 0
+    }
+
+    /// USB 2.0 only
+    pub fn enable_changed(&self) -> bool {
+        !self.superspeed && self.change & change::ENABLE != 0
+    }
+
+    /// USB 2.0 only
+    pub fn suspend_changed(&self) -> bool {
+        !self.superspeed && self.change & change::SUSPEND != 0
+    }
+
+    pub fn over_current_changed(&self) -> bool {
+        self.change & change::OVER_CURRENT != 0
+    }
+
+    pub fn reset_changed(&self) -> bool {
+        self.change & change::RESET != 0
+    }
+
+    /// SuperSpeed only
+    pub fn bh_reset_changed(&self) -> bool {
+        self.superspeed && self.change & change::BH_RESET != 0
+    }
+
+    /// SuperSpeed only
+    pub fn link_state_changed(&self) -> bool {
+        self.superspeed && self.change & change::LINK_STATE != 0
+    }
+
+    /// SuperSpeed only
+    pub fn config_error_changed(&self) -> bool {
+        self.superspeed && self.change & change::CONFIG_ERROR != 0
+    }
+
+    /// Whether `change` is set. Always `false` for bits this kind of hub
+    /// doesn't have.
+    pub fn has_changed(&self, change: Change) -> bool {
+        change.applies_to(self.superspeed) && self.change & change.bit() != 0
+    }
+
+    /// Every change bit that is set, meaning it changed since it was last
+    /// acknowledged.
+    pub fn changed(&self) -> Vec<Change> {
+        Change::ALL
+            .into_iter()
+            .filter(|change| self.has_changed(*change))
+            .collect()
+    }
+
+    /// Names of every change bit that is set.
+    pub fn changes(&self) -> Vec<&'static str> {
+        self.changed().into_iter().map(Change::name).collect()
+    }
+}
+
+impl core::fmt::Display for PortStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", if self.powered() { "ON" } else { "off" })?;
+        if self.connected() {
+            write!(f, ", connected")?;
+        }
+        if let Some(speed) = self.speed() {
+            write!(f, ", {speed}")?;
+        }
+        if self.enabled() {
+            write!(f, ", enabled")?;
+        }
+        if let Some(link_state) = self.link_state() {
+            write!(f, ", {link_state}")?;
+            if self.suspended() {
+                write!(f, " (suspended)")?;
+            }
+        } else if self.suspended() {
+            write!(f, ", suspended")?;
+        }
+        if self.over_current() {
+            write!(f, ", OVER-CURRENT")?;
+        }
+        if self.resetting() {
+            write!(f, ", resetting")?;
+        }
+        if self.test_mode() {
+            write!(f, ", test mode")?;
+        }
+        if self.indicator_control() {
+            write!(f, ", indicator control")?;
+        }
+        let changed = self.changed();
+        if !changed.is_empty() {
+            let changed: Vec<String> = changed.iter().map(Change::to_string).collect();
+            write!(
+                f,
+                " (changed since last acknowledged: {})",
+                changed.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode `wPortStatus` and `wPortChange` as a hub would send them.
+    fn decode(status: u16, change: u16, superspeed: bool) -> PortStatus {
+        let [s0, s1] = status.to_le_bytes();
+        let [c0, c1] = change.to_le_bytes();
+        PortStatus::from_bytes(&[s0, s1, c0, c1], superspeed).unwrap()
+    }
+
+    #[test]
+    fn decodes_usb2_port_status() {
+        // Powered, high-speed, connected and enabled, just plugged in.
+        let status = decode(0x0503, 0x0001, false);
+        assert!(status.powered());
+        assert!(status.connected());
+        assert!(status.enabled());
+        assert!(!status.suspended());
+        assert_eq!(status.speed(), Some(PortSpeed::High));
+        assert_eq!(status.link_state(), None);
+        assert_eq!(status.changed(), [Change::Connection]);
+        assert_eq!(
+            status.to_string(),
+            "ON, connected, high-speed, enabled (changed since last acknowledged: connection)"
+        );
+
+        for (word, speed) in [
+            (0x0301, PortSpeed::Low),
+            (0x0101, PortSpeed::Full),
+            (0x0501, PortSpeed::High),
+        ] {
+            assert_eq!(decode(word, 0, false).speed(), Some(speed), "{word:#06x}");
+        }
+
+        let status = decode(0x1907, 0x0004, false);
+        assert!(status.suspended());
+        assert!(status.test_mode());
+        assert!(status.indicator_control());
+        assert_eq!(status.changed(), [Change::Suspend]);
+
+        // Over-current and reset, with nothing connected.
+        let status = decode(0x0118, 0x0018, false);
+        assert!(status.over_current());
+        assert!(status.resetting());
+        assert_eq!(status.speed(), None);
+        assert_eq!(status.changed(), [Change::OverCurrent, Change::Reset]);
+
+        assert!(!decode(0x0000, 0, false).powered());
+        assert!(PortStatus::from_bytes(&[0x01, 0x01, 0x00], false).is_none());
+    }
+
+    #[test]
+    fn decodes_superspeed_port_status() {
+        // Powered, connected and enabled in U0 at speed ID 0.
+        let status = decode(0x0203, 0x0000, true);
+        assert!(status.powered());
+        assert!(status.connected());
+        assert!(status.enabled());
+        assert_eq!(status.link_state(), Some(LinkState::U0));
+        assert_eq!(status.speed(), Some(PortSpeed::SuperSpeed(0)));
+
+        // Power is bit 9, not bit 8 as on USB 2.0 hubs: bit 8 is the top
+        // bit of the link state, here Recovery.
+        let status = decode(0x0100, 0x0000, true);
+        assert!(!status.powered());
+        assert_eq!(status.link_state(), Some(LinkState::Recovery));
+        assert!(decode(0x0100, 0x0000, false).powered());
+
+        for (state, link_state) in [
+            (0x0, LinkState::U0),
+            (0x3, LinkState::U3),
+            (0x4, LinkState::Disabled),
+            (0x5, LinkState::RxDetect),
+            (0xb, LinkState::Loopback),
+            (0xf, LinkState::Reserved(0xf)),
+        ] {
+            let status = decode(0x0200 | state << 5, 0, true);
+            assert_eq!(status.link_state(), Some(link_state), "{state:#x}");
+            assert!(status.powered(), "{state:#x}");
+        }
+
+        // Suspended is U3, and bit 2 isn't suspend on SuperSpeed hubs.
+        let status = decode(0x0263, 0x0000, true);
+        assert!(status.suspended());
+        assert!(!decode(0x0207, 0x0000, true).suspended());
+
+        // Speed ID from bits 10-12, and link-state and BH reset changes.
+        let status = decode(0x0603, 0x0060, true);
+        assert_eq!(status.speed(), Some(PortSpeed::SuperSpeed(1)));
+        assert_eq!(status.changed(), [Change::BhReset, Change::LinkState]);
+        // USB 2.0 only change bits are left out.
+        assert_eq!(decode(0x0203, 0x0006, true).changed(), []);
+        // And USB 2.0 only status bits.
+        let status = decode(0x1a03, 0x0000, true);
+        assert!(!status.test_mode());
+        assert!(!status.indicator_control());
+    }
+
+    #[test]
+    fn decodes_hub_status() {
+        let status = HubStatus::from_bytes(&[0x01, 0x00, 0x03, 0x00]).unwrap();
+        assert!(status.local_power_lost());
+        assert!(!status.over_current());
+        assert_eq!(
+            status.changed(),
+            [HubChange::LocalPower, HubChange::OverCurrent]
+        );
+        assert_eq!(
+            status.changes(),
+            ["C_HUB_LOCAL_POWER", "C_HUB_OVER_CURRENT"]
+        );
+        assert_eq!(status.acknowledged().changed(), []);
+
+        let status = HubStatus::from_bytes(&[0x02, 0x00, 0x02, 0x00]).unwrap();
+        assert!(!status.local_power_lost());
+        assert!(status.over_current());
+        assert_eq!(status.changed(), [HubChange::OverCurrent]);
+        assert!(HubStatus::from_bytes(&[0x00, 0x00]).is_none());
+    }
+}
@@ -0,0 +1,326 @@
+//! Finding the hubs on the system and the devices attached to their ports.
+
+use std::time::Duration;
+
+use usb_ids::FromId;
+
+use crate::bos::ContainerId;
+use crate::control::HubControl;
+use crate::descriptor::AnyHubDescriptor;
+use crate::device::{self, Device, UsbDevice};
+use crate::error::Error;
+use crate::selector::{HubSelector, Location, LookupError, PortSelector, hub_port_of};
+
+/// How long to wait for power to settle when the hub doesn't say.
+pub const DEFAULT_POWER_GOOD_DELAY: Duration = Duration::from_millis(100);
+
+/// The device class of every hub.
+pub const USB_CLASS_HUB: u8 = 0x09;
+
+/// A hub, along with whatever is plugged into each of its ports.
+#[derive(Debug, Clone)]
+pub struct Hub<D = Device> {
+    index: usize,
+    info: D,
+    descriptor: Option<AnyHubDescriptor>,
+    container_id: Option<ContainerId>,
+    children: Vec<Option<D>>,
+}
+
+impl Hub {
+    /// Read the hub's descriptor and Container ID, and find the devices
+    /// attached to its ports. `index` is its position among the hubs in
+    /// `devices`.
+    pub async fn describe(index: usize, info: &Device, devices: &[Device]) -> Self {
+        let (descriptor, container_id) = match HubControl::new(info).await {
+            Ok(control) => (
+                control
+                    .descriptor()
+                    .await
+                    .inspect_err(|e| log::debug!("Couldn't read hub descriptor: {e}"))
+                    .ok(),
+                control
+                    .container_id()
+                    .await
+                    .inspect_err(|e| log::debug!("Couldn't read hub BOS descriptor: {e}"))
+                    .ok()
+                    .flatten(),
+            ),
+            Err(e) => {
+                log::debug!("Couldn't open hub: {e}");
+                (None, None)
+            }
+        };
+        Hub::new(index, info, descriptor, devices).with_container_id(container_id)
+    }
+}
+
+impl<D: UsbDevice> Hub<D> {
+    /// Find the devices attached to the ports of a hub whose descriptor has
+    /// already been read. The hub has no ports if it has no descriptor.
+    pub fn new(
+        index: usize,
+        info: &D,
+        descriptor: Option<AnyHubDescriptor>,
+        devices: &[D],
+    ) -> Self {
+        let mut children = vec![];
+        if let Some(descriptor) = &descriptor {
+            children.resize(descriptor.port_count() as usize, None);
+            for child_device in devices {
+                let Some(port_number) = hub_port_of(info, child_device) else {
+                    continue;
+                };
+                if port_number == 0 {
+                    log::error!("Port number is 0!");
+                    continue;
+                }
+                if let Some(child) = children.get_mut(port_number as usize - 1) {
+                    *child = Some(child_device.clone());
+                }
+            }
+        } else {
+            log::warn!("Can't inquire port count from hub {}", hub_name(info));
+        }
+
+        Hub {
+            index,
+            info: info.clone(),
+            descriptor,
+            container_id: None,
+            children,
+        }
+    }
+
+    /// Set the Container ID read from the hub's BOS descriptor.
+    pub fn with_container_id(mut self, container_id: Option<ContainerId>) -> Self {
+        self.container_id = container_id;
+        self
+    }
+
+    /// Position in the `hubctl list` output
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn info(&self) -> &D {
+        &self.info
+    }
+
+    /// The hub's descriptor, or `None` if it couldn't be read.
+    pub fn descriptor(&self) -> Option<&AnyHubDescriptor> {
+        self.descriptor.as_ref()
+    }
+
+    /// The Container ID from the hub's BOS descriptor, or `None` if it
+    /// doesn't have one or it couldn't be read.
+    pub fn container_id(&self) -> Option<ContainerId> {
+        self.container_id
+    }
+
+    /// The device attached to each port, if any, starting with port 1.
+    /// Empty if the hub's descriptor couldn't be read.
+    pub fn children(&self) -> &[Option<D>] {
+        &self.children
+    }
+
+    pub fn port_count(&self) -> u8 {
+        self.children.len() as u8
+    }
+
+    /// How long to wait after powering a port before its power is good.
+    pub fn power_good_delay(&self) -> Duration {
+        self.descriptor
+            .as_ref()
+            .map(|d| d.power_good_delay())
+            .unwrap_or(DEFAULT_POWER_GOOD_DELAY)
+    }
+
+    /// Make sure `port` exists on this hub.
+    pub fn check_port(&self, port: u8) -> Result<(), LookupError> {
+        if port == 0 || port > self.port_count() {
+            return Err(LookupError::NoSuchPort(port, self.children.len()));
+        }
+        Ok(())
+    }
+
+    /// A one-line description of the hub.
+    pub fn name(&self) -> String {
+        hub_name(&self.info)
+    }
+}
+
+impl<D: UsbDevice> core::fmt::Display for Hub<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())?;
+        if let Some(descriptor) = &self.descriptor {
+            write!(
+                f,
+                " [{} power switching]",
+                descriptor.characteristics().power_switching()
+            )?;
+        }
+        writeln!(f)?;
+        for (index, child) in self.children.iter().enumerate() {
+            writeln!(f, "    {}: {}", index + 1, device_name(child.as_ref()))?;
+        }
+        Ok(())
+    }
+}
+
+fn hub_name(device_info: &impl UsbDevice) -> String {
+    format!(
+        "Hub {:04x}:{:04x} {} / {} / {} ({} / {}) @ {}",
+        device_info.vendor_id(),
+        device_info.product_id(),
+        device_info.product_string().unwrap_or("[no product name]"),
+        device_info
+            .manufacturer_string()
+            .unwrap_or("[no manufacturer]"),
+        device_info.serial_number().unwrap_or("[no serial number]"),
+        usb_ids::Vendor::from_id(device_info.vendor_id())
+            .map(|v| v.name())
+            .unwrap_or("[unknown vendor]"),
+        usb_ids::Device::from_vid_pid(device_info.vendor_id(), device_info.product_id())
+            .map(|v| v.name())
+            .unwrap_or("[unknown product]"),
+        Location::of(device_info)
+    )
+}
+
+/// Name the device attached to a hub port, preferring its name in usb-ids.
+pub fn device_name(child: Option<&impl UsbDevice>) -> String {
+    let Some(child) = child else {
+        return "<no device>".to_owned();
+    };
+    usb_ids::Device::from_vid_pid(child.vendor_id(), child.product_id())
+        .map(|v| v.name().to_owned())
+        .or_else(|| {
+            child.product_string().map(|ps| {
+                format!(
+                    "{ps} from {}",
+                    usb_ids::Vendor::from_id(child.vendor_id())
+                        .map(|v| v.name())
+                        .unwrap_or("[unknown vendor]")
+                )
+            })
+        })
+        .unwrap_or_else(|| "<unknown>".to_owned())
+}
+
+/// Every hub among `devices`, in the order `hubctl list` shows them.
+pub fn hub_infos<D: UsbDevice>(devices: &[D]) -> Vec<D> {
+    devices
+        .iter()
+        .filter(|device_info| device_info.class() == USB_CLASS_HUB)
+        .cloned()
+        .collect()
+}
+
+/// Enumerate every hub among `devices` along with the devices attached to
+/// each of its ports.
+pub async fn discover(devices: &[Device]) -> Vec<Hub> {
+    let mut hubs = vec![];
+    for (index, device_info) in hub_infos(devices).iter().enumerate() {
+        hubs.push(Hub::describe(index, device_info, devices).await);
+    }
+    hubs
+}
+
+/// Pick out the single hub on the system that `selector` refers to, along
+/// with the port that `port` refers to on it.
+pub async fn find(
+    selector: &HubSelector,
+    port: Option<&PortSelector>,
+) -> Result<(Hub, Option<u8>), Error> {
+    let devices = device::list().await?;
+    let hubs = hub_infos(&devices);
+    let index = selector.select(&hubs, &devices)?;
+    let port = port
+        .map(|port| port.select(&hubs[index], &devices))
+        .transpose()?;
+    let hub = Hub::describe(index, &hubs[index], &devices).await;
+    if let Some(port) = port {
+        hub.check_port(port)?;
+    }
+    Ok((hub, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::PowerSwitching;
+    use crate::sim::{SimDevice, SimHub};
+
+    #[tokio::test]
+    async fn children_are_placed_on_their_ports() {
+        let hub = SimHub::test_hub(4);
+        hub.attach(1, SimDevice::new(0x1366, 0x0105).with_product("J-Link"));
+        hub.attach(4, SimDevice::new(0x0403, 0x6001));
+
+        let topology = hub.describe(&hub.devices()).await;
+        assert_eq!(topology.port_count(), 4);
+        let ports: Vec<Option<&[u8]>> = topology
+            .children()
+            .iter()
+            .map(|child| child.as_ref().map(|child| child.port_chain()))
+            .collect();
+        assert_eq!(ports, [Some(&[2, 1][..]), None, None, Some(&[2, 4][..])]);
+        assert_eq!(
+            topology.children()[0].as_ref().unwrap().product_string(),
+            Some("J-Link")
+        );
+    }
+
+    #[tokio::test]
+    async fn unpowered_ports_are_empty() {
+        let hub = SimHub::test_hub(2);
+        hub.attach(2, SimDevice::new(0x1366, 0x0105));
+        HubControl::with_transport(hub.clone(), false)
+            .off(2)
+            .await
+            .unwrap();
+
+        let topology = hub.describe(&hub.devices()).await;
+        assert!(topology.children().iter().all(Option::is_none));
+    }
+
+    #[tokio::test]
+    async fn nested_hubs_only_claim_their_own_children() {
+        let upstream = SimHub::test_hub(4);
+        let downstream = SimHub::new(
+            SimDevice::hub("1", &[2, 3], 0x05e3, 0x0608),
+            4,
+            PowerSwitching::Ganged,
+        );
+        upstream.attach(3, downstream.info().clone());
+        downstream.attach(1, SimDevice::new(0x1366, 0x0105));
+
+        let mut devices = upstream.devices();
+        devices.extend(downstream.devices().into_iter().skip(1));
+        assert_eq!(hub_infos(&devices).len(), 2);
+
+        let upper = upstream.describe(&devices).await;
+        assert_eq!(upper.index(), 0);
+        assert_eq!(upper.children()[2].as_ref(), Some(downstream.info()));
+        assert!(upper.children()[0].is_none());
+
+        let lower = downstream.describe(&devices).await;
+        assert_eq!(lower.index(), 1);
+        assert_eq!(
+            lower.children()[0].as_ref().map(|child| child.port_chain()),
+            Some(&[2, 3, 1][..])
+        );
+    }
+
+    #[test]
+    fn hub_without_descriptor_has_no_ports() {
+        let info = SimDevice::hub("1", &[2], 0x0424, 0x2514);
+        let hub = Hub::new(0, &info, None, std::slice::from_ref(&info));
+        assert_eq!(hub.port_count(), 0);
+        assert!(matches!(
+            hub.check_port(1),
+            Err(LookupError::NoSuchPort(1, 0))
+        ));
+    }
+}
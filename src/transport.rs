@@ -0,0 +1,102 @@
+//! The control transfers [`HubControl`](crate::HubControl) needs from a hub,
+//! so that it can talk to something other than real hardware.
+
+use std::future::Future;
+use std::time::Duration;
+
+use nusb::transfer::{ControlIn, ControlOut, TransferError};
+
+use crate::record::{Recorder, Replay};
+
+/// Something that can carry control transfers to a hub.
+pub trait Transport {
+    fn control_in(
+        &self,
+        data: ControlIn,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Vec<u8>, TransferError>> + Send;
+
+    fn control_out(
+        &self,
+        data: ControlOut<'_>,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<(), TransferError>> + Send;
+}
+
+/// The handle used to talk to real hubs. Windows platforms must go through
+/// the Interface. Other platforms may not even allow claiming the Interface.
+#[cfg(windows)]
+pub type UsbHandle = nusb::Interface;
+#[cfg(not(windows))]
+pub type UsbHandle = nusb::Device;
+
+/// A real hub, or the replay of a recorded one.
+#[derive(Clone)]
+pub enum Backend {
+    Usb(UsbHandle),
+    Replay(Replay),
+}
+
+impl Transport for Backend {
+    async fn control_in(
+        &self,
+        data: ControlIn,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, TransferError> {
+        match self {
+            Backend::Usb(handle) => handle.control_in(data, timeout).await,
+            Backend::Replay(replay) => replay.control_in(data, timeout).await,
+        }
+    }
+
+    async fn control_out(
+        &self,
+        data: ControlOut<'_>,
+        timeout: Duration,
+    ) -> Result<(), TransferError> {
+        match self {
+            Backend::Usb(handle) => handle.control_out(data, timeout).await,
+            Backend::Replay(replay) => replay.control_out(data, timeout).await,
+        }
+    }
+}
+
+/// The transport [`HubControl::new`](crate::HubControl::new) uses, which
+/// records transfers when a session recording has been started.
+pub type UsbTransport = Recorder<Backend>;
+
+impl Transport for nusb::Device {
+    async fn control_in(
+        &self,
+        data: ControlIn,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, TransferError> {
+        nusb::Device::control_in(self, data, timeout).await
+    }
+
+    async fn control_out(
+        &self,
+        data: ControlOut<'_>,
+        timeout: Duration,
+    ) -> Result<(), TransferError> {
+        nusb::Device::control_out(self, data, timeout).await
+    }
+}
+
+impl Transport for nusb::Interface {
+    async fn control_in(
+        &self,
+        data: ControlIn,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, TransferError> {
+        nusb::Interface::control_in(self, data, timeout).await
+    }
+
+    async fn control_out(
+        &self,
+        data: ControlOut<'_>,
+        timeout: Duration,
+    ) -> Result<(), TransferError> {
+        nusb::Interface::control_out(self, data, timeout).await
+    }
+}
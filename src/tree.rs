@@ -0,0 +1,419 @@
+//! Arranging hubs into the tree they're cabled in, from each bus down
+//! through cascaded hubs to the devices at the ends.
+
+use crate::control::HubControl;
+use crate::device::{Device, UsbDevice};
+use crate::selector::Location;
+use crate::status::PortStatus;
+use crate::topology::{self, Hub};
+
+/// Every bus on the system, with the hubs and devices below it.
+#[derive(Debug, Clone)]
+pub struct Tree<D = Device> {
+    buses: Vec<Bus<D>>,
+}
+
+/// A bus, and whatever is attached to it that isn't below a known hub.
+/// This is normally just the root hub, but platforms that don't list root
+/// hubs put the devices on the root ports here instead.
+#[derive(Debug, Clone)]
+pub struct Bus<D = Device> {
+    id: String,
+    nodes: Vec<Node<D>>,
+}
+
+/// Something in the tree: either a hub with its ports, or any other device.
+#[derive(Debug, Clone)]
+pub enum Node<D = Device> {
+    Hub(HubNode<D>),
+    Device(D),
+}
+
+/// A hub along with the power state of each of its ports and whatever is
+/// plugged into them.
+#[derive(Debug, Clone)]
+pub struct HubNode<D = Device> {
+    hub: Hub<D>,
+    ports: Vec<Port<D>>,
+}
+
+/// One downstream port of a hub.
+#[derive(Debug, Clone)]
+pub struct Port<D = Device> {
+    number: u8,
+    status: Option<PortStatus>,
+    attached: Option<Node<D>>,
+}
+
+/// A hub, and the status of each of its ports where it could be read.
+type Described<D> = (Hub<D>, Vec<Option<PortStatus>>);
+
+impl Tree {
+    /// Describe every hub among `devices`, read the status of each of their
+    /// ports, and arrange them into a tree.
+    pub async fn read(devices: &[Device]) -> Self {
+        let mut hubs = vec![];
+        for hub in topology::discover(devices).await {
+            let mut statuses = vec![];
+            match HubControl::new(hub.info()).await {
+                Ok(control) => {
+                    for port in 1..=hub.port_count() {
+                        statuses.push(control.status(port).await.ok());
+                    }
+                }
+                Err(e) => log::debug!("Couldn't open hub: {e}"),
+            }
+            hubs.push((hub, statuses));
+        }
+        Tree::new(hubs, devices)
+    }
+}
+
+impl<D: UsbDevice> Tree<D> {
+    /// Arrange hubs, each with the status of its ports, into a tree. Any of
+    /// `devices` that no hub has on its ports goes directly on its bus.
+    pub fn new(hubs: impl IntoIterator<Item = Described<D>>, devices: &[D]) -> Self {
+        let mut hubs: Vec<Option<Described<D>>> = hubs.into_iter().map(Some).collect();
+        let placed: Vec<Location> = hubs
+            .iter()
+            .flatten()
+            .flat_map(|(hub, _)| hub.children().iter().flatten())
+            .map(Location::of)
+            .collect();
+
+        let mut roots: Vec<&D> = devices
+            .iter()
+            .filter(|device| !placed.contains(&Location::of(*device)))
+            .collect();
+        roots.sort_by(|a, b| {
+            bus_order(a.bus_id())
+                .cmp(&bus_order(b.bus_id()))
+                .then_with(|| a.port_chain().cmp(b.port_chain()))
+        });
+
+        let mut buses: Vec<Bus<D>> = vec![];
+        for device in roots {
+            let node = build(device, &mut hubs);
+            match buses.last_mut() {
+                Some(bus) if bus.id == device.bus_id() => bus.nodes.push(node),
+                _ => buses.push(Bus {
+                    id: device.bus_id().to_owned(),
+                    nodes: vec![node],
+                }),
+            }
+        }
+        Tree { buses }
+    }
+
+    pub fn buses(&self) -> &[Bus<D>] {
+        &self.buses
+    }
+
+    /// Every hub in the tree, depth first, along with how many hubs are
+    /// above it.
+    pub fn hubs(&self) -> Vec<(usize, &HubNode<D>)> {
+        fn visit<'a, D>(node: &'a Node<D>, depth: usize, out: &mut Vec<(usize, &'a HubNode<D>)>) {
+            if let Node::Hub(hub) = node {
+                out.push((depth, hub));
+                for port in &hub.ports {
+                    if let Some(attached) = &port.attached {
+                        visit(attached, depth + 1, out);
+                    }
+                }
+            }
+        }
+        let mut hubs = vec![];
+        for bus in &self.buses {
+            for node in &bus.nodes {
+                visit(node, 0, &mut hubs);
+            }
+        }
+        hubs
+    }
+}
+
+/// Put the node for `device` together, taking its hub out of `hubs` if it
+/// is one so that it can't appear in the tree twice.
+fn build<D: UsbDevice>(device: &D, hubs: &mut [Option<Described<D>>]) -> Node<D> {
+    let location = Location::of(device);
+    let Some((hub, statuses)) = hubs
+        .iter_mut()
+        .find(|hub| {
+            hub.as_ref()
+                .is_some_and(|(hub, _)| Location::of(hub.info()) == location)
+        })
+        .and_then(Option::take)
+    else {
+        return Node::Device(device.clone());
+    };
+    let ports = hub
+        .children()
+        .iter()
+        .enumerate()
+        .map(|(index, child)| Port {
+            number: index as u8 + 1,
+            status: statuses.get(index).copied().flatten(),
+            attached: child.as_ref().map(|child| build(child, hubs)),
+        })
+        .collect();
+    Node::Hub(HubNode { hub, ports })
+}
+
+/// Sort numbered buses by number rather than as strings.
+fn bus_order(id: &str) -> (Option<u32>, &str) {
+    (id.parse().ok(), id)
+}
+
+impl<D> Bus<D> {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The hubs and devices attached directly to the bus.
+    pub fn nodes(&self) -> &[Node<D>] {
+        &self.nodes
+    }
+}
+
+impl<D: UsbDevice> Node<D> {
+    /// The device this node stands for.
+    pub fn info(&self) -> &D {
+        match self {
+            Node::Hub(hub) => hub.hub.info(),
+            Node::Device(device) => device,
+        }
+    }
+
+    fn label(&self) -> String {
+        match self {
+            Node::Hub(hub) => hub.label(),
+            Node::Device(device) => topology::device_name(Some(device)),
+        }
+    }
+
+    fn write_tree(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+        let Node::Hub(hub) = self else {
+            return Ok(());
+        };
+        for port in &hub.ports {
+            let power = match port.status {
+                Some(status) if status.over_current() => "off, OVER-CURRENT",
+                Some(status) if status.powered() => "ON",
+                Some(_) => "off",
+                None => "?",
+            };
+            let name = port
+                .attached
+                .as_ref()
+                .map(Node::label)
+                .unwrap_or_else(|| "<no device>".to_owned());
+            writeln!(
+                f,
+                "{:indent$}|__ Port {} [{power}]: {name}",
+                "",
+                port.number,
+                indent = depth * 4
+            )?;
+            if let Some(attached) = &port.attached {
+                attached.write_tree(f, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<D> HubNode<D> {
+    pub fn hub(&self) -> &Hub<D> {
+        &self.hub
+    }
+
+    /// Each port of the hub, starting with port 1.
+    pub fn ports(&self) -> &[Port<D>] {
+        &self.ports
+    }
+}
+
+impl<D> Port<D> {
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+
+    /// The port's status, or `None` if it couldn't be read.
+    pub fn status(&self) -> Option<PortStatus> {
+        self.status
+    }
+
+    /// Whatever is plugged into the port.
+    pub fn attached(&self) -> Option<&Node<D>> {
+        self.attached.as_ref()
+    }
+}
+
+impl<D: UsbDevice> HubNode<D> {
+    /// The hub's name followed by how it switches power.
+    pub fn label(&self) -> String {
+        match self.hub.descriptor() {
+            Some(descriptor) => format!(
+                "{} [{} power switching]",
+                self.hub.name(),
+                descriptor.characteristics().power_switching()
+            ),
+            None => self.hub.name(),
+        }
+    }
+}
+
+impl<D: UsbDevice> core::fmt::Display for Tree<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for bus in &self.buses {
+            match bus.id.parse::<u32>() {
+                Ok(number) => writeln!(f, "/: Bus {number}")?,
+                Err(_) => writeln!(f, "/: Bus {}", bus.id)?,
+            }
+            for node in &bus.nodes {
+                match node.info().port_chain().last() {
+                    Some(port) => writeln!(f, "    |__ Port {port}: {}", node.label())?,
+                    None => writeln!(f, "    |__ {}", node.label())?,
+                }
+                node.write_tree(f, 2)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::PowerSwitching;
+    use crate::sim::{SimDevice, SimHub};
+    use crate::topology::hub_infos;
+
+    /// Describe each simulated hub and read its port status, the way
+    /// [`Tree::read`] does for real hubs.
+    async fn read(hubs: &[&SimHub], devices: &[SimDevice]) -> Tree<SimDevice> {
+        let mut described = vec![];
+        for sim in hubs {
+            described.push(sim.describe_with_status(devices).await);
+        }
+        Tree::new(described, devices)
+    }
+
+    fn cascade() -> (SimHub, SimHub) {
+        let upstream = SimHub::test_hub(3);
+        let downstream = SimHub::new(
+            SimDevice::hub("1", &[2, 3], 0x05e3, 0x0608),
+            2,
+            PowerSwitching::Ganged,
+        );
+        upstream.attach(3, downstream.info().clone());
+        upstream.attach(1, SimDevice::new(0x0403, 0x6001));
+        downstream.attach(2, SimDevice::new(0x1366, 0x0105).with_product("J-Link"));
+        (upstream, downstream)
+    }
+
+    fn devices(upstream: &SimHub, downstream: &SimHub) -> Vec<SimDevice> {
+        let mut devices = upstream.devices();
+        devices.extend(downstream.devices().into_iter().skip(1));
+        devices
+    }
+
+    #[tokio::test]
+    async fn cascaded_hubs_nest() {
+        let (upstream, downstream) = cascade();
+        let devices = devices(&upstream, &downstream);
+        let tree = read(&[&downstream, &upstream], &devices).await;
+
+        assert_eq!(tree.buses().len(), 1);
+        let bus = &tree.buses()[0];
+        assert_eq!(bus.id(), "1");
+        let [Node::Hub(top)] = bus.nodes() else {
+            panic!("expected only the upstream hub on the bus");
+        };
+        assert_eq!(top.hub().info(), upstream.info());
+        assert_eq!(top.ports().len(), 3);
+        assert!(matches!(top.ports()[0].attached(), Some(Node::Device(_))));
+        assert!(top.ports()[1].attached().is_none());
+        let Some(Node::Hub(lower)) = top.ports()[2].attached() else {
+            panic!("expected the downstream hub on port 3");
+        };
+        assert_eq!(lower.hub().info(), downstream.info());
+        assert_eq!(
+            lower.ports()[1]
+                .attached()
+                .map(|node| node.info().port_chain()),
+            Some(&[2, 3, 2][..])
+        );
+
+        let depths: Vec<(usize, u16)> = tree
+            .hubs()
+            .into_iter()
+            .map(|(depth, hub)| (depth, hub.hub().info().product_id()))
+            .collect();
+        assert_eq!(depths, [(0, 0x2514), (1, 0x0608)]);
+    }
+
+    #[tokio::test]
+    async fn ports_carry_their_power_state() {
+        let (upstream, downstream) = cascade();
+        HubControl::with_transport(upstream.clone(), false)
+            .off(2)
+            .await
+            .unwrap();
+        let devices = devices(&upstream, &downstream);
+        let tree = read(&[&upstream, &downstream], &devices).await;
+
+        let Node::Hub(top) = &tree.buses()[0].nodes()[0] else {
+            panic!("expected a hub");
+        };
+        let powered: Vec<Option<bool>> = top
+            .ports()
+            .iter()
+            .map(|port| port.status().map(|status| status.powered()))
+            .collect();
+        assert_eq!(powered, [Some(true), Some(false), Some(true)]);
+
+        let text = tree.to_string();
+        assert!(text.starts_with("/: Bus 1\n    |__ Port 2: Hub 0424:2514"));
+        assert!(text.contains("\n        |__ Port 2 [off]: <no device>\n"));
+        assert!(text.contains("\n            |__ Port 2 [ON]: "));
+    }
+
+    #[tokio::test]
+    async fn devices_without_a_known_hub_hang_off_the_bus() {
+        let (upstream, downstream) = cascade();
+        let mut devices = devices(&upstream, &downstream);
+        let mut other = SimDevice::new(0x046d, 0xc52b);
+        other.bus_id = "10".to_owned();
+        other.port_chain = vec![1];
+        devices.push(other);
+        let tree = read(&[&upstream, &downstream], &devices).await;
+
+        let ids: Vec<&str> = tree.buses().iter().map(Bus::id).collect();
+        assert_eq!(ids, ["1", "10"]);
+        assert!(matches!(
+            tree.buses()[1].nodes(),
+            [Node::Device(device)] if device.product_id() == 0xc52b
+        ));
+    }
+
+    #[tokio::test]
+    async fn hubs_without_descriptors_are_leaves() {
+        let (upstream, downstream) = cascade();
+        let devices = devices(&upstream, &downstream);
+        let infos = hub_infos(&devices);
+        let hubs = infos
+            .iter()
+            .enumerate()
+            .map(|(index, info)| (Hub::new(index, info, None, &devices), vec![]));
+        let tree = Tree::new(hubs, &devices);
+
+        // With no ports to put them on, everything ends up on the bus.
+        assert_eq!(tree.buses()[0].nodes().len(), devices.len());
+        assert!(
+            tree.hubs()
+                .iter()
+                .all(|(depth, hub)| *depth == 0 && hub.ports().is_empty())
+        );
+    }
+}
@@ -0,0 +1,307 @@
+//! Following what happens on hub ports over time: devices coming and going,
+//! and ports losing power or reporting over-current.
+
+use std::collections::HashMap;
+
+use nusb::{DeviceId, DeviceInfo, hotplug::HotplugEvent};
+use serde::Serialize;
+
+use crate::device::{Device, UsbDevice};
+use crate::events::{AnyChange, HubEvent, PortEvent};
+use crate::report;
+use crate::selector::Location;
+
+/// Something that happened on a hub port. `hub` is the location of the hub,
+/// written as `--location` takes it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A device was plugged in or powered up. `hub` and `port` are `None`
+    /// for root hubs.
+    Connected {
+        hub: Option<String>,
+        port: Option<u8>,
+        device: report::Device,
+    },
+    /// A device went away. Everything is `None` if the device was never
+    /// seen connected.
+    Disconnected {
+        hub: Option<String>,
+        port: Option<u8>,
+        device: Option<report::Device>,
+    },
+    /// A port was switched on or off.
+    Power {
+        hub: String,
+        port: u8,
+        powered: bool,
+    },
+    /// A port started or stopped reporting over-current.
+    OverCurrent { hub: String, port: u8, active: bool },
+    /// A port that tripped over-current was powered again, and either stayed
+    /// powered or kept tripping until the attempts ran out.
+    Recovery {
+        hub: String,
+        port: u8,
+        recovered: bool,
+        attempts: u32,
+    },
+    /// Anything else that happened to a port, such as it being suspended.
+    Port {
+        hub: String,
+        port: u8,
+        change: PortEvent,
+    },
+    /// The hub as a whole lost or regained its external power supply, or
+    /// started or stopped reporting over-current on all of its ports.
+    HubStatus { hub: String, change: HubEvent },
+}
+
+impl Event {
+    pub fn connected(device: &impl UsbDevice) -> Self {
+        let (hub, port) = attachment(device);
+        Event::Connected {
+            hub,
+            port,
+            device: report::Device::from(device),
+        }
+    }
+
+    pub fn disconnected(device: Option<&impl UsbDevice>) -> Self {
+        let (hub, port) = device.map(attachment).unwrap_or_default();
+        Event::Disconnected {
+            hub,
+            port,
+            device: device.map(report::Device::from),
+        }
+    }
+
+    /// The event for a change on the hub at `hub` or one of its ports.
+    /// Connections are left out, as hotplug events already report them.
+    pub fn from_change(hub: &str, change: &AnyChange) -> Option<Self> {
+        let hub = hub.to_owned();
+        let change = match change {
+            AnyChange::Port(change) => change,
+            AnyChange::Hub(change) => {
+                return Some(Event::HubStatus {
+                    hub,
+                    change: change.event,
+                });
+            }
+        };
+        let port = change.port;
+        Some(match change.event {
+            PortEvent::Connected | PortEvent::Disconnected => return None,
+            PortEvent::PowerOn => Event::Power {
+                hub,
+                port,
+                powered: true,
+            },
+            PortEvent::PowerOff => Event::Power {
+                hub,
+                port,
+                powered: false,
+            },
+            PortEvent::OverCurrent => Event::OverCurrent {
+                hub,
+                port,
+                active: true,
+            },
+            PortEvent::OverCurrentCleared => Event::OverCurrent {
+                hub,
+                port,
+                active: false,
+            },
+            change => Event::Port { hub, port, change },
+        })
+    }
+}
+
+/// The hub and port a device is plugged into.
+fn attachment(device: &impl UsbDevice) -> (Option<String>, Option<u8>) {
+    match Location::of(device).parent() {
+        Some((hub, port)) => (Some(hub.to_string()), Some(port)),
+        None => (None, None),
+    }
+}
+
+impl core::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |device: &report::Device| {
+            format!(
+                "{:04x}:{:04x} {}",
+                device.vendor_id,
+                device.product_id,
+                device
+                    .product_name
+                    .or(device.product.as_deref())
+                    .unwrap_or("<unknown>")
+            )
+        };
+        let place = |hub: &Option<String>, port: &Option<u8>| match (hub, port) {
+            (Some(hub), Some(port)) => format!("{hub} port {port}"),
+            _ => "root hub".to_owned(),
+        };
+        match self {
+            Event::Connected { hub, port, device } => {
+                write!(f, "{}: connected {}", place(hub, port), describe(device))
+            }
+            Event::Disconnected {
+                device: Some(device),
+                hub,
+                port,
+            } => write!(f, "{}: disconnected {}", place(hub, port), describe(device)),
+            Event::Disconnected { device: None, .. } => write!(f, "unknown device disconnected"),
+            Event::Power { hub, port, powered } => write!(
+                f,
+                "{hub} port {port}: power {}",
+                if *powered { "ON" } else { "off" }
+            ),
+            Event::Recovery {
+                hub,
+                port,
+                recovered: true,
+                attempts,
+            } => write!(
+                f,
+                "{hub} port {port}: powered again after over-current (attempt {attempts})"
+            ),
+            Event::Recovery {
+                hub,
+                port,
+                recovered: false,
+                attempts,
+            } => write!(
+                f,
+                "{hub} port {port}: still over-current after {attempts} attempts, leaving it off"
+            ),
+            Event::Port { hub, port, change } => write!(f, "{hub} port {port}: {change}"),
+            Event::HubStatus { hub, change } => write!(f, "{hub}: hub {change}"),
+            Event::OverCurrent { hub, port, active } => write!(
+                f,
+                "{hub} port {port}: {}",
+                if *active {
+                    "OVER-CURRENT"
+                } else {
+                    "over-current cleared"
+                }
+            ),
+        }
+    }
+}
+
+/// Keeps track of connected devices, so that disconnections, which nusb
+/// only reports by ID, can say which device went away and where it was.
+pub struct Devices {
+    known: HashMap<DeviceId, DeviceInfo>,
+}
+
+impl Devices {
+    pub fn new(devices: &[Device]) -> Self {
+        Devices {
+            known: devices
+                .iter()
+                .filter_map(Device::info)
+                .map(|device| (device.id(), device.clone()))
+                .collect(),
+        }
+    }
+
+    /// Update the set of devices, and describe the hotplug event along with
+    /// the device it was about.
+    pub fn update(&mut self, event: HotplugEvent) -> (Event, Option<DeviceInfo>) {
+        match event {
+            HotplugEvent::Connected(device) => {
+                self.known.insert(device.id(), device.clone());
+                (Event::connected(&device), Some(device))
+            }
+            HotplugEvent::Disconnected(id) => {
+                let device = self.known.remove(&id);
+                (Event::disconnected(device.as_ref()), device)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HubControl;
+    use crate::events::HubEvents;
+    use crate::sim::{SimDevice, SimHub};
+
+    #[tokio::test]
+    async fn port_changes_are_reported() {
+        let sim = SimHub::test_hub(4);
+        let control = HubControl::with_transport(sim.clone(), false);
+        let hub = sim.describe(&sim.devices()).await;
+        let mut events = HubEvents::new(&hub, control, sim.clone()).await;
+
+        sim.attach(2, SimDevice::new(0x1366, 0x0105));
+        let reported: Vec<String> = events
+            .next()
+            .await
+            .unwrap()
+            .iter()
+            .filter_map(|change| Event::from_change(events.location(), change))
+            .map(|event| event.to_string())
+            .collect();
+        assert_eq!(reported, ["1-2 port 2: enabled"]);
+
+        sim.set_over_current(1, true);
+        let reported: Vec<serde_json::Value> = events
+            .next()
+            .await
+            .unwrap()
+            .iter()
+            .filter_map(|change| Event::from_change(events.location(), change))
+            .map(|event| serde_json::to_value(event).unwrap())
+            .collect();
+        assert_eq!(
+            reported,
+            [
+                serde_json::json!({"event": "power", "hub": "1-2", "port": 1, "powered": false}),
+                serde_json::json!({"event": "over_current", "hub": "1-2", "port": 1, "active": true}),
+            ]
+        );
+
+        sim.set_hub_over_current(true);
+        let reported: Vec<Event> = events
+            .next()
+            .await
+            .unwrap()
+            .iter()
+            .filter_map(|change| Event::from_change(events.location(), change))
+            .collect();
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].to_string(), "1-2: hub OVER-CURRENT");
+        assert_eq!(
+            serde_json::to_value(&reported[0]).unwrap(),
+            serde_json::json!({"event": "hub_status", "hub": "1-2", "change": "over_current"})
+        );
+    }
+
+    #[test]
+    fn hotplug_events_name_the_hub_port() {
+        let device = SimDevice::hub("1", &[2, 3], 0x05e3, 0x0608);
+        let json = serde_json::to_value(Event::connected(&device)).unwrap();
+        assert_eq!(json["event"], "connected");
+        assert_eq!(json["hub"], "1-2");
+        assert_eq!(json["port"], 3);
+        assert_eq!(json["device"]["location"], "1-2.3");
+
+        let root = SimDevice::hub("1", &[], 0x1d6b, 0x0002);
+        assert!(matches!(
+            Event::disconnected(Some(&root)),
+            Event::Disconnected {
+                hub: None,
+                port: None,
+                device: Some(_)
+            }
+        ));
+        assert_eq!(
+            Event::disconnected(None::<&SimDevice>).to_string(),
+            "unknown device disconnected"
+        );
+    }
+}